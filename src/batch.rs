@@ -0,0 +1,167 @@
+use crate::event::LogEntry;
+use std::borrow::Borrow;
+
+/// Maximum number of events allowed in a single PutLogEvents call.
+pub const MAX_BATCH_EVENTS: usize = 10_000;
+
+/// Maximum size of a PutLogEvents call, in bytes.
+/// The size is calculated as the sum of all event messages in UTF-8,
+/// plus 26 bytes for each log event.
+pub const MAX_BATCH_BYTES: usize = 1_048_576;
+
+/// Bytes that CloudWatch adds to the size of every event in a batch.
+pub const EVENT_OVERHEAD_BYTES: usize = 26;
+
+/// Maximum size of a single event, including its overhead.
+pub const MAX_EVENT_BYTES: usize = 262_144;
+
+/// Maximum time span between the oldest and the newest event in a batch.
+pub const MAX_BATCH_SPAN_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// Size that an event takes in a PutLogEvents batch.
+pub fn event_size(event: &LogEntry) -> usize {
+    event.message.len() + EVENT_OVERHEAD_BYTES
+}
+
+/// Remove the events larger than `MAX_EVENT_BYTES`, which PutLogEvents
+/// would reject along with the rest of their batch.
+/// Returns the remaining events and the number of events removed.
+pub fn drop_oversized<T: Borrow<LogEntry>>(events: Vec<T>) -> (Vec<T>, usize) {
+    let total = events.len();
+    let events: Vec<T> = events
+        .into_iter()
+        .filter(|event| event_size(event.borrow()) <= MAX_EVENT_BYTES)
+        .collect();
+    let dropped = total - events.len();
+    (events, dropped)
+}
+
+/// Split a list of events into chunks that respect the PutLogEvents limits.
+///
+/// Chunks keep the original order of the events, and every chunk
+/// contains at least one event. Oversized events must be removed first
+/// with `drop_oversized`.
+pub fn plan_batches<T: Borrow<LogEntry>>(events: &[T]) -> Vec<&[T]> {
+    let mut batches = Vec::new();
+
+    let mut start = 0;
+    let mut bytes = 0;
+    let mut oldest = i64::MAX;
+    let mut newest = i64::MIN;
+
+    for (idx, event) in events.iter().enumerate() {
+        let event = event.borrow();
+        let size = event_size(event);
+        let span = newest.max(event.timestamp) - oldest.min(event.timestamp);
+
+        let full = idx - start >= MAX_BATCH_EVENTS
+            || bytes + size > MAX_BATCH_BYTES
+            || span > MAX_BATCH_SPAN_MILLIS;
+
+        if full && idx > start {
+            batches.push(&events[start..idx]);
+            start = idx;
+            bytes = 0;
+            oldest = i64::MAX;
+            newest = i64::MIN;
+        }
+
+        bytes += size;
+        oldest = oldest.min(event.timestamp);
+        newest = newest.max(event.timestamp);
+    }
+
+    if start < events.len() {
+        batches.push(&events[start..]);
+    }
+
+    batches
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(timestamp: i64, message: &str) -> LogEntry {
+        LogEntry {
+            timestamp,
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_batches_empty() {
+        let events: Vec<&LogEntry> = Vec::new();
+        assert!(plan_batches(&events).is_empty());
+    }
+
+    #[test]
+    fn test_plan_batches_single_batch() {
+        let entries = [entry(0, "a"), entry(1, "b"), entry(2, "c")];
+        let events: Vec<&LogEntry> = entries.iter().collect();
+
+        let batches = plan_batches(&events);
+        assert_eq!(1, batches.len());
+        assert_eq!(3, batches[0].len());
+    }
+
+    #[test]
+    fn test_plan_batches_by_count() {
+        let entries = vec![entry(0, "a"); MAX_BATCH_EVENTS + 1];
+        let events: Vec<&LogEntry> = entries.iter().collect();
+
+        let batches = plan_batches(&events);
+        assert_eq!(2, batches.len());
+        assert_eq!(MAX_BATCH_EVENTS, batches[0].len());
+        assert_eq!(1, batches[1].len());
+    }
+
+    #[test]
+    fn test_plan_batches_by_size() {
+        // Each event takes half of the batch, including the overhead
+        let message = "x".repeat(MAX_BATCH_BYTES / 2 - EVENT_OVERHEAD_BYTES);
+        let entries = [entry(0, &message), entry(0, &message), entry(0, "a")];
+        let events: Vec<&LogEntry> = entries.iter().collect();
+
+        let batches = plan_batches(&events);
+        assert_eq!(2, batches.len());
+        assert_eq!(2, batches[0].len());
+        assert_eq!(1, batches[1].len());
+    }
+
+    #[test]
+    fn test_plan_batches_by_time_span() {
+        let entries = [
+            entry(0, "a"),
+            entry(MAX_BATCH_SPAN_MILLIS, "b"),
+            entry(MAX_BATCH_SPAN_MILLIS + 1, "c"),
+        ];
+        let events: Vec<&LogEntry> = entries.iter().collect();
+
+        let batches = plan_batches(&events);
+        assert_eq!(2, batches.len());
+        assert_eq!(2, batches[0].len());
+        assert_eq!("c", batches[1][0].message);
+    }
+
+    #[test]
+    fn test_plan_batches_oversized_event() {
+        let largest = "x".repeat(MAX_EVENT_BYTES - EVENT_OVERHEAD_BYTES);
+        let oversized = "x".repeat(MAX_EVENT_BYTES - EVENT_OVERHEAD_BYTES + 1);
+        let entries = [
+            entry(0, "a"),
+            entry(0, &oversized),
+            entry(0, &largest),
+            entry(0, "b"),
+        ];
+
+        let (events, dropped) = drop_oversized(entries.iter().collect::<Vec<&LogEntry>>());
+        assert_eq!(1, dropped);
+        assert!(events.iter().all(|event| event.message != oversized));
+
+        let batches = plan_batches(&events);
+        assert_eq!(1, batches.len());
+        assert_eq!(3, batches[0].len());
+    }
+}
@@ -1,6 +1,11 @@
 use aws_sdk_sts::Client as StsClient;
-use cloudwatch_log_processor::{handle_logs, sts, DynamoDBClient, LogsEvent};
+use cloudwatch_log_processor::{
+    handle_firehose_events, handle_kinesis_events, handle_logs, handle_retry_messages, sts, Config,
+    CredentialCache, DynamoDBClient, EventSource, FileStore, FirehoseEvent, FunctionInfoCache,
+    FunctionInfoStore, KinesisEvent, LogsEvent, RetryQueue, SqsEvent, StreamCache,
+};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::path::Path;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -11,20 +16,100 @@ async fn main() -> Result<(), Error> {
         .init();
 
     // Get AWS Configuration
-    let config = aws_config::load_from_env().await;
-    let sts_client = StsClient::new(&config);
+    let sdk_config = aws_config::load_from_env().await;
+    let sts_client = StsClient::new(&sdk_config);
 
-    let dynamodb_table =
-        std::env::var("DYNAMODB_TABLE").expect("missing environment variable DYNAMODB_TABLE");
-    let dynamodb_assume_role = std::env::var("DYNAMODB_ASSUME_ROLE")
-        .expect("missing environment variable DYNAMODB_ASSUME_ROLE");
+    let config = Config::from_env()?;
+    let retry_queue = config
+        .retry_queue_url
+        .as_ref()
+        .map(|queue_url| RetryQueue::new(&sdk_config, queue_url));
+    let cache = StreamCache::new();
+    let credentials = CredentialCache::new();
 
-    let session_id = format!("cloudwatch_logs_processor_session_{}", uuid::Uuid::new_v4());
-    let dynamodb_config = sts::assume_role(&sts_client, &session_id, &dynamodb_assume_role).await?;
-    let dynamodb_client = DynamoDBClient::new(&dynamodb_config, &dynamodb_table).await;
+    // Read the functions from a file when one is configured, and from DynamoDB otherwise
+    let function_store: Box<dyn FunctionInfoStore> = match std::env::var("FUNCTION_STORE_FILE") {
+        Ok(path) if !path.is_empty() => Box::new(FileStore::load(Path::new(&path))?),
+        _ => {
+            let dynamodb_table = std::env::var("DYNAMODB_TABLE")
+                .expect("missing environment variable DYNAMODB_TABLE");
+            let dynamodb_assume_role = std::env::var("DYNAMODB_ASSUME_ROLE")
+                .expect("missing environment variable DYNAMODB_ASSUME_ROLE");
 
-    run(service_fn(|event: LambdaEvent<LogsEvent>| {
-        handle_logs(&sts_client, &dynamodb_client, event)
-    }))
-    .await
+            let session_name =
+                sts::session_name("dynamodb", &uuid::Uuid::new_v4().simple().to_string());
+            let provider =
+                sts::AssumeRoleProvider::new(&sts_client, &session_name, &dynamodb_assume_role);
+            let dynamodb_config = sts::assumed_config(provider).await?;
+            let dynamodb_client = DynamoDBClient::new(&dynamodb_config, &dynamodb_table)
+                .await
+                .with_cache(FunctionInfoCache::new(
+                    config.function_cache_ttl,
+                    config.function_cache_negative_ttl,
+                    config.function_cache_max_size,
+                ));
+            Box::new(dynamodb_client)
+        }
+    };
+
+    match config.event_source {
+        EventSource::CloudWatchLogs => {
+            run(service_fn(|event: LambdaEvent<LogsEvent>| {
+                handle_logs(
+                    &sts_client,
+                    &credentials,
+                    function_store.as_ref(),
+                    &config,
+                    &cache,
+                    retry_queue.as_ref(),
+                    event,
+                )
+            }))
+            .await
+        }
+        EventSource::Kinesis => {
+            run(service_fn(|event: LambdaEvent<KinesisEvent>| {
+                handle_kinesis_events(
+                    &sts_client,
+                    &credentials,
+                    function_store.as_ref(),
+                    &config,
+                    &cache,
+                    retry_queue.as_ref(),
+                    event,
+                )
+            }))
+            .await
+        }
+        EventSource::Firehose => {
+            run(service_fn(|event: LambdaEvent<FirehoseEvent>| {
+                handle_firehose_events(
+                    &sts_client,
+                    &credentials,
+                    function_store.as_ref(),
+                    &config,
+                    &cache,
+                    event,
+                )
+            }))
+            .await
+        }
+        EventSource::RetryQueue => {
+            let retry_queue = retry_queue
+                .as_ref()
+                .ok_or("missing environment variable RETRY_QUEUE_URL")?;
+            run(service_fn(|event: LambdaEvent<SqsEvent>| {
+                handle_retry_messages(
+                    &sts_client,
+                    &credentials,
+                    function_store.as_ref(),
+                    &config,
+                    &cache,
+                    retry_queue,
+                    event,
+                )
+            }))
+            .await
+        }
+    }
 }
@@ -1,49 +1,216 @@
-use aws_sdk_cloudwatchlogs::{Client, Error};
+use async_trait::async_trait;
+use aws_sdk_cloudwatchlogs::{
+    model::{InputLogEvent, LogGroup},
+    output::PutLogEventsOutput,
+    Client, Error,
+};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
 
-use crate::{error::RuntimeError, event::LogEntry};
+use crate::{
+    batch::{drop_oversized, plan_batches},
+    config::Config,
+    error::RuntimeError,
+    event::LogEntry,
+    filter::LogFilter,
+    normalize::{normalize_events, now_millis, NormalizeReport},
+    report::{rejected_events, DeliveryReport, LogGroupDrift},
+    sink::{LogSink, SinkDestination},
+    stream_cache::StreamCache,
+};
+
+/// `Destination` is the log stream in the customer account
+/// where the events are delivered
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Destination {
+    /// Id of the customer account
+    pub account: String,
+    /// Log group in the customer account
+    pub log_group: String,
+    /// Log stream in the customer account
+    pub log_stream: String,
+}
+
+/// Retention periods, in days, accepted by PutRetentionPolicy
+const RETENTION_DAYS: &[i32] = &[
+    1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365, 400, 545, 731, 1096, 1827, 2192, 2557, 2922,
+    3288, 3653,
+];
+
+/// Check if CloudWatch accepts a retention period
+pub fn is_valid_retention(days: i32) -> bool {
+    RETENTION_DAYS.contains(&days)
+}
+
+/// `LogGroupSettings` are applied to the log groups in the customer account
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogGroupSettings {
+    /// Number of days that CloudWatch keeps the events, None keeps them forever
+    pub retention_in_days: Option<i32>,
+    /// KMS key used to encrypt the events
+    pub kms_key_id: Option<String>,
+    /// Tags added to the log group, for cost allocation
+    pub tags: HashMap<String, String>,
+}
+
+/// Find a log group in the customer account by its exact name.
+/// DescribeLogGroups only supports prefix searches,
+/// so this function follows every page of results.
+#[tracing::instrument(skip(client))]
+async fn find_log_group(
+    client: &Client,
+    log_group: &str,
+) -> Result<Option<LogGroup>, RuntimeError> {
+    let mut next_token = None;
+
+    loop {
+        let res = client
+            .describe_log_groups()
+            .log_group_name_prefix(log_group)
+            .set_next_token(next_token)
+            .send()
+            .await;
+
+        let output = match res {
+            Ok(output) => output,
+            Err(sdk_err) => {
+                let err = sdk_err.into();
+                match err {
+                    Error::ResourceNotFoundException(_) => return Ok(None),
+                    _ => return Err(RuntimeError::CloudWatchLogs(err)),
+                }
+            }
+        };
+
+        let group = output
+            .log_groups
+            .unwrap_or_default()
+            .into_iter()
+            .find(|g| g.log_group_name() == Some(log_group));
+        if group.is_some() {
+            return Ok(group);
+        }
+
+        match output.next_token {
+            Some(token) => next_token = Some(token),
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Compare the KMS key and the tags of an existing log group with the settings.
+/// Tags in the log group that are not in the settings are not considered drift.
+#[tracing::instrument(skip(client, group, settings))]
+async fn find_log_group_drift(
+    client: &Client,
+    group: &LogGroup,
+    settings: &LogGroupSettings,
+) -> Result<Vec<LogGroupDrift>, RuntimeError> {
+    let mut drift = Vec::new();
+
+    if settings.kms_key_id.is_some() && group.kms_key_id != settings.kms_key_id {
+        drift.push(LogGroupDrift::KmsKeyId {
+            expected: settings.kms_key_id.clone(),
+            actual: group.kms_key_id.clone(),
+        });
+    }
+
+    if !settings.tags.is_empty() {
+        let tags = client
+            .list_tags_log_group()
+            .set_log_group_name(group.log_group_name.clone())
+            .send()
+            .await
+            .map_err(Error::from)?
+            .tags
+            .unwrap_or_default();
+
+        let mut keys: Vec<&String> = settings.tags.keys().collect();
+        keys.sort();
+        for key in keys {
+            let actual = tags.get(key);
+            if actual != settings.tags.get(key) {
+                drift.push(LogGroupDrift::Tag {
+                    key: key.clone(),
+                    expected: settings.tags[key].clone(),
+                    actual: actual.cloned(),
+                });
+            }
+        }
+    }
+
+    Ok(drift)
+}
 
 /// Find a log group in the customer account that matches the
 /// function's log group.
-/// Create the group if it doesn't exist.
-#[tracing::instrument(skip(client))]
+/// Create the group if it doesn't exist, and make sure that
+/// its retention policy matches the settings.
+/// Differences in the KMS key and the tags of existing groups are returned
+/// as drift, they are not changed.
+/// Groups found in the cache are not checked again.
+#[tracing::instrument(skip(client, cache))]
 pub async fn create_new_log_group_if_missing(
     client: &Client,
+    cache: &StreamCache,
+    account: &str,
     log_group: &str,
-) -> Result<(), RuntimeError> {
-    let res = client
-        .describe_log_groups()
-        .log_group_name_prefix(log_group)
-        .send()
-        .await;
+    settings: &LogGroupSettings,
+) -> Result<Vec<LogGroupDrift>, RuntimeError> {
+    if cache.has_group(account, log_group) {
+        return Ok(Vec::new());
+    }
+
+    let mut drift = Vec::new();
+    let current_retention = match find_log_group(client, log_group).await? {
+        Some(group) => {
+            drift = find_log_group_drift(client, &group, settings).await?;
+            for d in &drift {
+                tracing::warn!(drift = ?d, "log group settings drifted");
+            }
+            group.retention_in_days
+        }
+        None => {
+            tracing::info!("creating new log group");
+            let tags = Some(settings.tags.clone()).filter(|t| !t.is_empty());
+            let res = client
+                .create_log_group()
+                .log_group_name(log_group)
+                .set_kms_key_id(settings.kms_key_id.clone())
+                .set_tags(tags)
+                .send()
+                .await;
 
-    let output = match res {
-        Ok(output) => match output.log_groups.filter(|g| g.is_empty()) {
-            Some(list) => list
-                .iter()
-                .filter_map(|g| g.log_group_name.clone())
-                .find(|s| s.as_str() == log_group),
-            None => None,
-        },
-        Err(sdk_err) => {
-            let err = sdk_err.into();
-            match err {
-                Error::ResourceNotFoundException(_) => None,
-                _ => return Err(RuntimeError::CloudWatchLogs(err)),
+            if let Err(sdk_err) = res {
+                let err = sdk_err.into();
+                match err {
+                    // another invocation created the group at the same time
+                    Error::ResourceAlreadyExistsException(_) => {}
+                    _ => return Err(RuntimeError::CloudWatchLogs(err)),
+                }
             }
+            None
         }
     };
 
-    if output.is_none() {
-        tracing::info!("creating new log group");
-        client
-            .create_log_group()
-            .log_group_name(log_group)
-            .send()
-            .await
-            .map_err(Error::from)?;
+    if let Some(days) = settings.retention_in_days {
+        if current_retention != Some(days) {
+            tracing::info!(days, "updating log group retention policy");
+            client
+                .put_retention_policy()
+                .log_group_name(log_group)
+                .retention_in_days(days)
+                .send()
+                .await
+                .map_err(Error::from)?;
+        }
     }
 
-    Ok(())
+    cache.add_group(account, log_group);
+    Ok(drift)
 }
 
 /// Find the next upload sequence token for the log stream.
@@ -56,87 +223,328 @@ async fn find_sequence_token(
     log_group: &str,
     log_stream: &str,
 ) -> Result<Option<String>, RuntimeError> {
-    let res = client
-        .describe_log_streams()
-        .log_group_name(log_group)
-        .log_stream_name_prefix(log_stream)
-        .send()
-        .await;
+    let mut next_token = None;
+
+    loop {
+        let res = client
+            .describe_log_streams()
+            .log_group_name(log_group)
+            .log_stream_name_prefix(log_stream)
+            .set_next_token(next_token)
+            .send()
+            .await;
 
-    let output = match res {
-        Ok(output) => Some(output),
-        Err(sdk_err) => {
-            let err = sdk_err.into();
-            match err {
-                Error::ResourceNotFoundException(_) => None,
-                _ => return Err(RuntimeError::CloudWatchLogs(err)),
+        let output = match res {
+            Ok(output) => output,
+            Err(sdk_err) => {
+                let err = sdk_err.into();
+                match err {
+                    Error::ResourceNotFoundException(_) => break,
+                    _ => return Err(RuntimeError::CloudWatchLogs(err)),
+                }
             }
+        };
+
+        let stream = output
+            .log_streams
+            .unwrap_or_default()
+            .into_iter()
+            .find(|s| s.log_stream_name() == Some(log_stream));
+        if let Some(stream) = stream {
+            return Ok(stream.upload_sequence_token);
         }
-    };
 
-    if let Some(streams) = output.and_then(|o| o.log_streams) {
-        for stream in streams {
-            if stream.log_stream_name().unwrap_or_default() == log_stream {
-                return Ok(stream.upload_sequence_token);
-            }
+        match output.next_token {
+            Some(token) => next_token = Some(token),
+            None => break,
         }
     }
 
     tracing::info!("creating new log stream");
-    client
+    let res = client
         .create_log_stream()
         .log_group_name(log_group)
         .log_stream_name(log_stream)
         .send()
-        .await
-        .map_err(Error::from)?;
+        .await;
+
+    if let Err(sdk_err) = res {
+        let err = sdk_err.into();
+        match err {
+            // another invocation created the stream at the same time,
+            // PutLogEvents will tell us the sequence token that it expects
+            Error::ResourceAlreadyExistsException(_) => {}
+            _ => return Err(RuntimeError::CloudWatchLogs(err)),
+        }
+    }
 
     Ok(None)
 }
 
-/// Send the log batch to the customer account
-#[tracing::instrument(skip(client, log_events))]
-pub async fn send_events(
+/// Maximum number of times that a batch is sent again
+/// after CloudWatch rejects its sequence token.
+const MAX_SEQUENCE_TOKEN_RETRIES: usize = 3;
+
+/// Send a single batch of events to CloudWatch.
+///
+/// Concurrent invocations writing to the same stream can invalidate
+/// the sequence token. When that happens, the batch is sent again
+/// with the token that CloudWatch expects.
+/// If CloudWatch already accepted the batch, it's considered delivered.
+#[tracing::instrument(skip(client, sequence_token, log_events))]
+async fn put_events(
     client: &Client,
     log_group: &str,
     log_stream: &str,
-    log_events: &Vec<LogEntry>,
+    mut sequence_token: Option<String>,
+    log_events: Vec<InputLogEvent>,
+) -> Result<PutLogEventsOutput, RuntimeError> {
+    let mut attempt = 0;
+
+    loop {
+        let res = client
+            .put_log_events()
+            .log_group_name(log_group)
+            .log_stream_name(log_stream)
+            .set_sequence_token(sequence_token)
+            .set_log_events(Some(log_events.clone()))
+            .send()
+            .await;
+
+        let err = match res {
+            Ok(output) => return Ok(output),
+            Err(sdk_err) => sdk_err.into(),
+        };
+
+        match err {
+            Error::InvalidSequenceTokenException(e) if attempt < MAX_SEQUENCE_TOKEN_RETRIES => {
+                tracing::warn!(attempt, "invalid sequence token, retrying");
+                attempt += 1;
+                sequence_token = e.expected_sequence_token;
+            }
+            Error::DataAlreadyAcceptedException(e) => {
+                tracing::warn!("batch already accepted");
+                return Ok(PutLogEventsOutput::builder()
+                    .set_next_sequence_token(e.expected_sequence_token)
+                    .build());
+            }
+            _ => return Err(RuntimeError::CloudWatchLogs(err)),
+        }
+    }
+}
+
+/// Send the log batch to the customer account.
+/// Events rejected by the filter are not delivered.
+/// The remaining events are sorted chronologically, and split in several
+/// PutLogEvents calls when they don't fit in a single request.
+/// The sequence token is taken from the cache when the stream is known.
+#[tracing::instrument(skip(client, config, cache, filter, log_events))]
+pub async fn send_events(
+    client: &Client,
+    config: &Config,
+    cache: &StreamCache,
+    filter: &LogFilter,
+    destination: &Destination,
+    log_events: &[LogEntry],
+    report: &mut DeliveryReport,
+) -> Result<(), RuntimeError> {
+    let events = prepare_events(config, filter, log_events, report);
+    put_prepared_events(client, cache, destination, &events, report).await
+}
+
+/// Select the events to submit: events skipped by the filter,
+/// dropped by the normalization or too large are counted in the report,
+/// and their ids recorded so they are not retried.
+fn prepare_events<'a>(
+    config: &Config,
+    filter: &LogFilter,
+    log_events: &'a [LogEntry],
+    report: &mut DeliveryReport,
+) -> Vec<Cow<'a, LogEntry>> {
+    let events: Vec<&LogEntry> = log_events.iter().filter(|e| filter.allows(e)).collect();
+    let filtered = log_events.len() - events.len();
+
+    let (events, normalized) = normalize_events(events, now_millis(), config.out_of_range_events);
+    if normalized != NormalizeReport::default() {
+        tracing::warn!(
+            reordered = normalized.reordered,
+            dropped = normalized.dropped,
+            clamped = normalized.clamped,
+            "events adjusted before delivery"
+        );
+    }
+
+    let (events, oversized) = drop_oversized(events);
+    if oversized > 0 {
+        tracing::warn!(oversized, "events too large for CloudWatch dropped");
+    }
+
+    let submitted: HashSet<&str> = events.iter().map(|event| event.id.as_str()).collect();
+    report.skipped_ids.extend(
+        log_events
+            .iter()
+            .filter(|event| !submitted.contains(event.id.as_str()))
+            .map(|event| event.id.clone()),
+    );
+    report.filtered += filtered;
+    report.normalized = normalized;
+    report.oversized += oversized;
+    events
+}
+
+/// Deliver the prepared events with as many PutLogEvents calls as needed
+async fn put_prepared_events(
+    client: &Client,
+    cache: &StreamCache,
+    destination: &Destination,
+    events: &[Cow<'_, LogEntry>],
+    report: &mut DeliveryReport,
 ) -> Result<(), RuntimeError> {
+    let Destination {
+        account,
+        log_group,
+        log_stream,
+    } = destination;
+
+    if events.is_empty() {
+        return Ok(());
+    }
+
     tracing::info!("sending logs to customer account");
 
-    let sequence_token = find_sequence_token(client, log_group, log_stream).await?;
+    let mut sequence_token = match cache.sequence_token(account, log_group, log_stream) {
+        Some(token) => token,
+        None => find_sequence_token(client, log_group, log_stream).await?,
+    };
 
-    let mut events_builder = client
-        .put_log_events()
-        .log_group_name(log_group)
-        .log_stream_name(log_stream)
-        .set_sequence_token(sequence_token);
+    for batch in plan_batches(events) {
+        let input = batch
+            .iter()
+            .map(|event| {
+                InputLogEvent::builder()
+                    .message(&event.message)
+                    .timestamp(event.timestamp)
+                    .build()
+            })
+            .collect();
 
-    for event in log_events {
-        if event.message.is_empty() || event.message.contains("Listening on port") {
-            continue;
-        }
+        let output = match put_events(client, log_group, log_stream, sequence_token, input).await {
+            Ok(output) => output,
+            Err(err) => {
+                if let RuntimeError::CloudWatchLogs(Error::ResourceNotFoundException(_)) = err {
+                    cache.invalidate_group(account, log_group);
+                } else {
+                    cache.invalidate_stream(account, log_group, log_stream);
+                }
+                return Err(err);
+            }
+        };
+        cache.set_sequence_token(
+            account,
+            log_group,
+            log_stream,
+            output.next_sequence_token.clone(),
+        );
 
-        let input = aws_sdk_cloudwatchlogs::model::InputLogEvent::builder()
-            .message(&event.message)
-            .timestamp(event.timestamp)
-            .build();
+        let rejected = output
+            .rejected_log_events_info
+            .map(|info| rejected_events(batch, &info))
+            .unwrap_or_default();
+        for event in &rejected {
+            tracing::warn!(id = %event.id, reason = ?event.reason, "event rejected by CloudWatch");
+        }
 
-        events_builder = events_builder.log_events(input);
+        sequence_token = output.next_sequence_token;
+        report.batches += 1;
+        report.events += batch.len() - rejected.len();
+        report.rejected.extend(rejected);
+        report
+            .sent_ids
+            .extend(batch.iter().map(|event| event.id.clone()));
     }
 
-    events_builder.send().await.map_err(Error::from)?;
+    tracing::info!(
+        batches = report.batches,
+        events = report.events,
+        rejected = report.rejected.len(),
+        "logs delivered"
+    );
 
     Ok(())
 }
 
+/// `CloudWatchLogsSink` delivers the events to a log stream in the customer account,
+/// creating its log group when it's missing
+pub struct CloudWatchLogsSink<'a> {
+    client: Client,
+    config: &'a Config,
+    cache: &'a StreamCache,
+    destination: Destination,
+    settings: LogGroupSettings,
+}
+
+impl<'a> CloudWatchLogsSink<'a> {
+    /// Create a sink for a log stream
+    pub fn new(
+        client: Client,
+        config: &'a Config,
+        cache: &'a StreamCache,
+        destination: Destination,
+        settings: LogGroupSettings,
+    ) -> CloudWatchLogsSink<'a> {
+        CloudWatchLogsSink {
+            client,
+            config,
+            cache,
+            destination,
+            settings,
+        }
+    }
+}
+
+#[async_trait]
+impl LogSink for CloudWatchLogsSink<'_> {
+    fn destination(&self) -> SinkDestination {
+        SinkDestination::CloudWatchLogs(self.destination.clone())
+    }
+
+    async fn send_events(
+        &self,
+        filter: &LogFilter,
+        log_events: &[LogEntry],
+        report: &mut DeliveryReport,
+    ) -> Result<(), RuntimeError> {
+        // the events are prepared first, so the events that would never be
+        // submitted are known even when the log group can't be created
+        let events = prepare_events(self.config, filter, log_events, report);
+
+        report.drift = create_new_log_group_if_missing(
+            &self.client,
+            self.cache,
+            &self.destination.account,
+            &self.destination.log_group,
+            &self.settings,
+        )
+        .await?;
+
+        put_prepared_events(&self.client, self.cache, &self.destination, &events, report).await
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{event::LogEntry, test_util::*};
+    use crate::{
+        config::Config as ProcessorConfig,
+        event::LogEntry,
+        filter::{FilterAction, FilterRule, Matcher},
+        report::{RejectedEvent, RejectionReason},
+        test_util::*,
+    };
     use aws_sdk_cloudwatchlogs::{Client, Config};
     use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
     use aws_smithy_http::body::SdkBody;
+    use std::collections::HashSet;
 
     #[tokio::test]
     async fn test_create_new_log_group_if_missing() -> Result<(), RuntimeError> {
@@ -171,121 +579,1062 @@ mod test {
         let config = Config::new(&get_mock_config().await);
         let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
 
-        create_new_log_group_if_missing(&client, "aws/amplify/compute/function").await?;
-        conn.assert_requests_match(&vec![]);
-
-        Ok(())
-    }
-
-    #[tokio::test]
-    async fn test_find_sequence_token_without_existent_stream() -> Result<(), RuntimeError> {
-        let conn = TestConnection::new(vec![(
-            get_request_builder("logs")
-                .header("content-type", "application/x-amz-json-1.1")
-                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
-                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
-                .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{\"logStreams\": []}"))
-                .unwrap(),
-        ),
-        (
-            get_request_builder("logs")
-                .header("content-type", "application/x-amz-json-1.1")
-                .header("x-amz-target", "Logs_20140328.CreateLogStream")
-                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamName\": \"stream_name\"}"))
-                .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
+        let cache = StreamCache::new();
+        create_new_log_group_if_missing(
+            &client,
+            &cache,
+            "123456789012",
+            "aws/amplify/compute/function",
+            &LogGroupSettings::default(),
         )
-        ]);
-        let config = Config::new(&get_mock_config().await);
-        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
-
-        let token =
-            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
-        assert_eq!(None, token);
-        conn.assert_requests_match(&vec![]);
+        .await?;
+        assert!(cache.has_group("123456789012", "aws/amplify/compute/function"));
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_find_sequence_token_with_existent_stream() -> Result<(), RuntimeError> {
-        let conn = TestConnection::new(vec![(
-            get_request_builder("logs")
-                .header("content-type", "application/x-amz-json-1.1")
-                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
-                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
-                .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
-                .unwrap(),
-        )
+    async fn test_create_new_log_group_if_missing_with_group_in_second_page(
+    ) -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"logGroups": [{"logGroupName": "aws/amplify/compute/function-1"}], "nextToken": "page-2"}"#))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        r#"{"logGroupNamePrefix":"aws/amplify/compute/function","nextToken":"page-2"}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"logGroups": [{"logGroupName": "aws/amplify/compute/function"}]}"#))
+                    .unwrap(),
+            ),
         ]);
         let config = Config::new(&get_mock_config().await);
         let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
 
-        let token =
-            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
-        assert_eq!(Some("upload_sequence_token".into()), token);
-        conn.assert_requests_match(&vec![]);
+        let cache = StreamCache::new();
+        create_new_log_group_if_missing(
+            &client,
+            &cache,
+            "123456789012",
+            "aws/amplify/compute/function",
+            &LogGroupSettings::default(),
+        )
+        .await?;
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_send_events() -> Result<(), RuntimeError> {
+    async fn test_create_new_log_group_if_missing_with_concurrent_creation(
+    ) -> Result<(), RuntimeError> {
         let conn = TestConnection::new(vec![
-        (
-            get_request_builder("logs")
-                .header("content-type", "application/x-amz-json-1.1")
-                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
-                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
-                .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
-                .unwrap(),
-        ),
-        (
-            get_request_builder("logs")
-                .header("content-type", "application/x-amz-json-1.1")
-                .header("x-amz-target", "Logs_20140328.PutLogEvents")
-                .body(SdkBody::from(r#"{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"upload_sequence_token","logEvents":[{"timestamp":0,"message":"GET /homepage"}]}"#))
-                .unwrap(),
-            http::Response::builder()
-                .status(200)
-                .body(SdkBody::from("{}"))
-                .unwrap(),
-        )
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{\"logGroups\": []}"))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.CreateLogGroup")
+                    .body(SdkBody::from(
+                        "{\"logGroupName\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(r#"{"__type": "ResourceAlreadyExistsException", "message": "The specified log group already exists"}"#))
+                    .unwrap(),
+            ),
         ]);
         let config = Config::new(&get_mock_config().await);
         let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
 
-        let mut events = Vec::new();
-        events.push(LogEntry {
-            message: "Listening on port 3000".into(),
-            ..Default::default()
-        });
-        events.push(LogEntry {
-            message: "GET /homepage".into(),
-            ..Default::default()
-        });
-
-        send_events(
+        let cache = StreamCache::new();
+        create_new_log_group_if_missing(
             &client,
+            &cache,
+            "123456789012",
             "aws/amplify/compute/function",
-            "stream_name",
-            &events,
+            &LogGroupSettings::default(),
         )
         .await?;
-        conn.assert_requests_match(&vec![]);
+        assert!(cache.has_group("123456789012", "aws/amplify/compute/function"));
+        conn.assert_requests_match(&[]);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_create_new_log_group_if_missing_with_retention() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{\"logGroups\": []}"))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.CreateLogGroup")
+                    .body(SdkBody::from(
+                        "{\"logGroupName\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.PutRetentionPolicy")
+                    .body(SdkBody::from(
+                        r#"{"logGroupName":"aws/amplify/compute/function","retentionInDays":30}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let settings = LogGroupSettings {
+            retention_in_days: Some(30),
+            ..Default::default()
+        };
+        create_new_log_group_if_missing(
+            &client,
+            &StreamCache::new(),
+            "123456789012",
+            "aws/amplify/compute/function",
+            &settings,
+        )
+        .await?;
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_new_log_group_if_missing_reconciles_retention() -> Result<(), RuntimeError>
+    {
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"logGroups": [{"logGroupName": "aws/amplify/compute/function", "retentionInDays": 7}]}"#))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.PutRetentionPolicy")
+                    .body(SdkBody::from(
+                        r#"{"logGroupName":"aws/amplify/compute/function","retentionInDays":30}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let settings = LogGroupSettings {
+            retention_in_days: Some(30),
+            ..Default::default()
+        };
+        create_new_log_group_if_missing(
+            &client,
+            &StreamCache::new(),
+            "123456789012",
+            "aws/amplify/compute/function",
+            &settings,
+        )
+        .await?;
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_new_log_group_if_missing_with_kms_key_and_tags() -> Result<(), RuntimeError>
+    {
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{\"logGroups\": []}"))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.CreateLogGroup")
+                    .body(SdkBody::from(
+                        r#"{"logGroupName":"aws/amplify/compute/function","kmsKeyId":"key","tags":{"team":"web"}}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let settings = LogGroupSettings {
+            kms_key_id: Some("key".into()),
+            tags: HashMap::from([("team".into(), "web".into())]),
+            ..Default::default()
+        };
+        let drift = create_new_log_group_if_missing(
+            &client,
+            &StreamCache::new(),
+            "123456789012",
+            "aws/amplify/compute/function",
+            &settings,
+        )
+        .await?;
+        assert!(drift.is_empty());
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_new_log_group_if_missing_reports_drift() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                    .body(SdkBody::from(
+                        "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"logGroups": [{"logGroupName": "aws/amplify/compute/function", "kmsKeyId": "old-key"}]}"#))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("logs")
+                    .header("content-type", "application/x-amz-json-1.1")
+                    .header("x-amz-target", "Logs_20140328.ListTagsLogGroup")
+                    .body(SdkBody::from(
+                        r#"{"logGroupName":"aws/amplify/compute/function"}"#,
+                    ))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"tags": {"team": "api", "owner": "me"}}"#))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let settings = LogGroupSettings {
+            kms_key_id: Some("key".into()),
+            tags: HashMap::from([
+                ("team".into(), "web".into()),
+                ("cost-center".into(), "42".into()),
+            ]),
+            ..Default::default()
+        };
+        let drift = create_new_log_group_if_missing(
+            &client,
+            &StreamCache::new(),
+            "123456789012",
+            "aws/amplify/compute/function",
+            &settings,
+        )
+        .await?;
+        assert_eq!(
+            vec![
+                LogGroupDrift::KmsKeyId {
+                    expected: Some("key".into()),
+                    actual: Some("old-key".into()),
+                },
+                LogGroupDrift::Tag {
+                    key: "cost-center".into(),
+                    expected: "42".into(),
+                    actual: None,
+                },
+                LogGroupDrift::Tag {
+                    key: "team".into(),
+                    expected: "web".into(),
+                    actual: Some("api".into()),
+                },
+            ],
+            drift
+        );
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_sequence_token_without_existent_stream() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": []}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.CreateLogStream")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamName\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let token =
+            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
+        assert_eq!(None, token);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_sequence_token_with_existent_stream() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let token =
+            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
+        assert_eq!(Some("upload_sequence_token".into()), token);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_sequence_token_with_stream_in_second_page() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"logStreams": [{"logStreamName": "stream_name_2", "uploadSequenceToken": "other_token"}], "nextToken": "page-2"}"#))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from(r#"{"logGroupName":"aws/amplify/compute/function", "logStreamNamePrefix": "stream_name", "nextToken": "page-2"}"#))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"logStreams": [{"logStreamName": "stream_name", "uploadSequenceToken": "upload_sequence_token"}]}"#))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let token =
+            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
+        assert_eq!(Some("upload_sequence_token".into()), token);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_sequence_token_with_concurrent_creation() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": []}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.CreateLogStream")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamName\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "ResourceAlreadyExistsException", "message": "The specified log stream already exists"}"#))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let token =
+            find_sequence_token(&client, "aws/amplify/compute/function", "stream_name").await?;
+        assert_eq!(None, token);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), RuntimeError> {
+        let now = now_millis();
+        let conn = TestConnection::new(vec![
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"upload_sequence_token","logEvents":[{{"timestamp":{now},"message":"GET /homepage"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let events = vec![
+            LogEntry {
+                message: "Listening on port 3000".into(),
+                ..Default::default()
+            },
+            LogEntry {
+                message: "GET /homepage".into(),
+                timestamp: now,
+                ..Default::default()
+            },
+        ];
+
+        let mut report = DeliveryReport::default();
+        send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &StreamCache::new(),
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut report,
+        )
+        .await?;
+        assert_eq!(1, report.batches);
+        assert_eq!(1, report.events);
+        assert_eq!(1, report.filtered);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_with_rejected_events() -> Result<(), RuntimeError> {
+        let now = now_millis();
+        let conn = TestConnection::new(vec![
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"upload_sequence_token","logEvents":[{{"timestamp":{now},"message":"GET /homepage"}},{{"timestamp":{now},"message":"GET /about"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"nextSequenceToken": "next_sequence_token", "rejectedLogEventsInfo": {"expiredLogEventEndIndex": 1}}"#))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let events = vec![
+            LogEntry {
+                id: "1".into(),
+                message: "GET /homepage".into(),
+                timestamp: now,
+            },
+            LogEntry {
+                id: "2".into(),
+                message: "GET /about".into(),
+                timestamp: now,
+            },
+        ];
+
+        let mut report = DeliveryReport::default();
+        send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &StreamCache::new(),
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut report,
+        )
+        .await?;
+        assert_eq!(1, report.events);
+        assert_eq!(
+            vec![RejectedEvent {
+                id: "1".into(),
+                reason: RejectionReason::Expired
+            }],
+            report.rejected
+        );
+        assert_eq!(
+            HashSet::from(["1".to_string(), "2".to_string()]),
+            report.sent_ids
+        );
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_in_multiple_batches() -> Result<(), RuntimeError> {
+        let now = now_millis();
+        let yesterday = now - 86400001;
+        let conn = TestConnection::new(vec![
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"upload_sequence_token","logEvents":[{{"timestamp":{yesterday},"message":"GET /homepage"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"nextSequenceToken": "next_sequence_token"}"#))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"next_sequence_token","logEvents":[{{"timestamp":{now},"message":"GET /about"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{}"))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        // events more than 24 hours apart cannot go in the same batch
+        let events = vec![
+            LogEntry {
+                message: "GET /homepage".into(),
+                timestamp: yesterday,
+                ..Default::default()
+            },
+            LogEntry {
+                message: "GET /about".into(),
+                timestamp: now,
+                ..Default::default()
+            },
+        ];
+
+        let mut report = DeliveryReport::default();
+        send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &StreamCache::new(),
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut report,
+        )
+        .await?;
+        assert_eq!(2, report.batches);
+        assert_eq!(2, report.events);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_partial_failure() -> Result<(), RuntimeError> {
+        let now = now_millis();
+        let yesterday = now - 86400001;
+        let conn = TestConnection::new(vec![
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogStreams")
+                .body(SdkBody::from("{\"logGroupName\":\"aws/amplify/compute/function\", \"logStreamNamePrefix\": \"stream_name\"}"))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from("{\"logStreams\": [{\"logStreamName\": \"stream_name\", \"uploadSequenceToken\": \"upload_sequence_token\"}]}"))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"upload_sequence_token","logEvents":[{{"timestamp":{yesterday},"message":"GET /homepage"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"nextSequenceToken": "next_sequence_token"}"#))
+                .unwrap(),
+        ),
+        (
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"next_sequence_token","logEvents":[{{"timestamp":{now},"message":"GET /about"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "InvalidSequenceTokenException", "message": "invalid token"}"#))
+                .unwrap(),
+        )
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let events = vec![
+            LogEntry {
+                id: "1".into(),
+                message: "GET /homepage".into(),
+                timestamp: yesterday,
+            },
+            LogEntry {
+                id: "2".into(),
+                message: "GET /about".into(),
+                timestamp: now,
+            },
+        ];
+
+        // the second batch fails, so only the first one is sent
+        let mut report = DeliveryReport::default();
+        let res = send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &StreamCache::new(),
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut report,
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(1, report.batches);
+        assert_eq!(HashSet::from(["1".to_string()]), report.sent_ids);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sink_skips_events_when_group_creation_fails() {
+        // GIVEN a batch with an event excluded by the filter and an event too old to deliver
+        let now = now_millis();
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.DescribeLogGroups")
+                .body(SdkBody::from(
+                    "{\"logGroupNamePrefix\":\"aws/amplify/compute/function\"}",
+                ))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(
+                    r#"{"__type": "InvalidParameterException", "message": "invalid"}"#,
+                ))
+                .unwrap(),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+        let cache = StreamCache::new();
+        let processor_config = ProcessorConfig::default();
+        let sink = CloudWatchLogsSink::new(
+            client,
+            &processor_config,
+            &cache,
+            destination(),
+            LogGroupSettings::default(),
+        );
+        let filter = LogFilter::new(vec![FilterRule {
+            action: FilterAction::Exclude,
+            matcher: Matcher::Prefix("START".into()),
+        }]);
+        let events = vec![
+            LogEntry {
+                id: "1".into(),
+                message: "START".into(),
+                timestamp: now,
+            },
+            LogEntry {
+                id: "2".into(),
+                message: "GET /homepage".into(),
+                timestamp: 0,
+            },
+            LogEntry {
+                id: "3".into(),
+                message: "GET /about".into(),
+                timestamp: now,
+            },
+        ];
+
+        // WHEN the log group can't be created
+        let mut report = DeliveryReport::default();
+        let res = sink.send_events(&filter, &events, &mut report).await;
+
+        // THEN only the event that was going to be submitted is left to retry
+        assert!(res.is_err());
+        assert_eq!(
+            HashSet::from(["1".to_string(), "2".to_string()]),
+            report.skipped_ids
+        );
+        assert!(report.sent_ids.is_empty());
+        conn.assert_requests_match(&[]);
+    }
+
+    fn destination() -> Destination {
+        Destination {
+            account: "123456789012".into(),
+            log_group: "aws/amplify/compute/function".into(),
+            log_stream: "stream_name".into(),
+        }
+    }
+
+    fn put_log_events_request(sequence_token: &str) -> http::Request<SdkBody> {
+        get_request_builder("logs")
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-target", "Logs_20140328.PutLogEvents")
+            .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"{sequence_token}","logEvents":[{{"timestamp":0,"message":"GET /homepage"}}]}}"#)))
+            .unwrap()
+    }
+
+    fn put_log_events_input() -> Vec<InputLogEvent> {
+        vec![InputLogEvent::builder()
+            .message("GET /homepage")
+            .timestamp(0)
+            .build()]
+    }
+
+    #[tokio::test]
+    async fn test_put_events_with_invalid_sequence_token() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![
+            (
+                put_log_events_request("stale_token"),
+                http::Response::builder()
+                    .status(400)
+                    .body(SdkBody::from(r#"{"__type": "InvalidSequenceTokenException", "expectedSequenceToken": "expected_token", "message": "The given sequenceToken is invalid"}"#))
+                    .unwrap(),
+            ),
+            (
+                put_log_events_request("expected_token"),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"nextSequenceToken": "next_sequence_token"}"#))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let output = put_events(
+            &client,
+            "aws/amplify/compute/function",
+            "stream_name",
+            Some("stale_token".into()),
+            put_log_events_input(),
+        )
+        .await?;
+        assert_eq!(Some("next_sequence_token"), output.next_sequence_token());
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_events_with_data_already_accepted() -> Result<(), RuntimeError> {
+        let conn = TestConnection::new(vec![(
+            put_log_events_request("stale_token"),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "DataAlreadyAcceptedException", "expectedSequenceToken": "expected_token", "message": "The given batch of log events has already been accepted"}"#))
+                .unwrap(),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let output = put_events(
+            &client,
+            "aws/amplify/compute/function",
+            "stream_name",
+            Some("stale_token".into()),
+            put_log_events_input(),
+        )
+        .await?;
+        assert_eq!(Some("expected_token"), output.next_sequence_token());
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_events_gives_up_after_max_retries() {
+        let events = (0..=MAX_SEQUENCE_TOKEN_RETRIES)
+            .map(|_| {
+                (
+                    put_log_events_request("stale_token"),
+                    http::Response::builder()
+                        .status(400)
+                        .body(SdkBody::from(r#"{"__type": "InvalidSequenceTokenException", "expectedSequenceToken": "stale_token", "message": "The given sequenceToken is invalid"}"#))
+                        .unwrap(),
+                )
+            })
+            .collect();
+        let conn = TestConnection::new(events);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let res = put_events(
+            &client,
+            "aws/amplify/compute/function",
+            "stream_name",
+            Some("stale_token".into()),
+            put_log_events_input(),
+        )
+        .await;
+        assert!(matches!(
+            res,
+            Err(RuntimeError::CloudWatchLogs(
+                Error::InvalidSequenceTokenException(_)
+            ))
+        ));
+        conn.assert_requests_match(&[]);
+    }
+
+    #[tokio::test]
+    async fn test_send_events_with_cached_stream() -> Result<(), RuntimeError> {
+        let now = now_millis();
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"cached_token","logEvents":[{{"timestamp":{now},"message":"GET /homepage"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(r#"{"nextSequenceToken": "next_sequence_token"}"#))
+                .unwrap(),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let cache = StreamCache::new();
+        cache.set_sequence_token(
+            "123456789012",
+            "aws/amplify/compute/function",
+            "stream_name",
+            Some("cached_token".into()),
+        );
+
+        let events = vec![LogEntry {
+            message: "GET /homepage".into(),
+            timestamp: now,
+            ..Default::default()
+        }];
+
+        send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &cache,
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut DeliveryReport::default(),
+        )
+        .await?;
+        assert_eq!(
+            Some(Some("next_sequence_token".into())),
+            cache.sequence_token(
+                "123456789012",
+                "aws/amplify/compute/function",
+                "stream_name"
+            )
+        );
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_events_invalidates_missing_stream() {
+        let now = now_millis();
+        let conn = TestConnection::new(vec![(
+            get_request_builder("logs")
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "Logs_20140328.PutLogEvents")
+                .body(SdkBody::from(format!(r#"{{"logGroupName":"aws/amplify/compute/function","logStreamName":"stream_name","sequenceToken":"cached_token","logEvents":[{{"timestamp":{now},"message":"GET /homepage"}}]}}"#)))
+                .unwrap(),
+            http::Response::builder()
+                .status(400)
+                .body(SdkBody::from(r#"{"__type": "ResourceNotFoundException", "message": "The specified log group does not exist."}"#))
+                .unwrap(),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let cache = StreamCache::new();
+        cache.add_group("123456789012", "aws/amplify/compute/function");
+        cache.set_sequence_token(
+            "123456789012",
+            "aws/amplify/compute/function",
+            "stream_name",
+            Some("cached_token".into()),
+        );
+
+        let events = vec![LogEntry {
+            message: "GET /homepage".into(),
+            timestamp: now,
+            ..Default::default()
+        }];
+
+        let res = send_events(
+            &client,
+            &ProcessorConfig::default(),
+            &cache,
+            &LogFilter::default(),
+            &destination(),
+            &events,
+            &mut DeliveryReport::default(),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(!cache.has_group("123456789012", "aws/amplify/compute/function"));
+        assert_eq!(
+            None,
+            cache.sequence_token(
+                "123456789012",
+                "aws/amplify/compute/function",
+                "stream_name"
+            )
+        );
+        conn.assert_requests_match(&[]);
+    }
 }
@@ -0,0 +1,277 @@
+use crate::{
+    cloudwatch_logs::is_valid_retention,
+    error::RuntimeError,
+    filter::LogFilter,
+    function_cache::{DEFAULT_MAX_SIZE, DEFAULT_NEGATIVE_TTL, DEFAULT_TTL},
+    function_id::{resolver_from_str, FunctionIdResolver, LastSegmentResolver},
+    naming::{
+        log_group_template, LogStreamStrategy, DEFAULT_LOG_GROUP_PREFIX, DEFAULT_LOG_GROUP_TEMPLATE,
+    },
+    normalize::OutOfRangePolicy,
+    template::Template,
+};
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+/// `EventSource` is the service that invokes the processor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventSource {
+    /// CloudWatch Logs subscription filters that invoke the function directly
+    #[default]
+    CloudWatchLogs,
+    /// Kinesis stream where the subscription filters publish the events
+    Kinesis,
+    /// Firehose delivery stream that uses the processor to transform its records
+    Firehose,
+    /// SQS queue with the batches that could not be delivered
+    RetryQueue,
+}
+
+impl FromStr for EventSource {
+    type Err = RuntimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cloudwatch_logs" => Ok(EventSource::CloudWatchLogs),
+            "kinesis" => Ok(EventSource::Kinesis),
+            "firehose" => Ok(EventSource::Firehose),
+            "retry_queue" => Ok(EventSource::RetryQueue),
+            _ => Err(RuntimeError::InvalidConfig(format!(
+                "unknown event source {s}"
+            ))),
+        }
+    }
+}
+
+/// `Config` holds the processor settings that don't change between invocations
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Service that invokes the processor
+    pub event_source: EventSource,
+    /// What to do with events outside of the CloudWatch acceptance window
+    pub out_of_range_events: OutOfRangePolicy,
+    /// Filter applied to the events of functions without their own filter
+    pub log_filter: LogFilter,
+    /// Retention applied to the log groups of functions without their own retention
+    pub default_retention_in_days: Option<i32>,
+    /// Value of the `{prefix}` variable in log group templates
+    pub log_group_prefix: String,
+    /// Template for the log group names of functions without their own template
+    pub log_group_template: Template,
+    /// Naming strategy for the log streams of functions without their own strategy
+    pub log_stream_strategy: LogStreamStrategy,
+    /// Extracts the function id from the source log group name
+    pub function_id_resolver: Arc<dyn FunctionIdResolver>,
+    /// Time that function information stays in the cache
+    pub function_cache_ttl: Duration,
+    /// Time that missing and invalid functions stay in the cache
+    pub function_cache_negative_ttl: Duration,
+    /// Maximum number of functions in the cache, 0 disables the cache
+    pub function_cache_max_size: usize,
+    /// URL of the SQS queue where the batches that could not be delivered are sent
+    pub retry_queue_url: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            event_source: EventSource::default(),
+            out_of_range_events: OutOfRangePolicy::default(),
+            log_filter: LogFilter::default(),
+            default_retention_in_days: None,
+            log_group_prefix: DEFAULT_LOG_GROUP_PREFIX.into(),
+            log_group_template: log_group_template(DEFAULT_LOG_GROUP_TEMPLATE)
+                .expect("invalid default log group template"),
+            log_stream_strategy: LogStreamStrategy::default(),
+            function_id_resolver: Arc::new(LastSegmentResolver),
+            function_cache_ttl: DEFAULT_TTL,
+            function_cache_negative_ttl: DEFAULT_NEGATIVE_TTL,
+            function_cache_max_size: DEFAULT_MAX_SIZE,
+            retry_queue_url: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load the processor settings from the environment.
+    /// Settings that are not present in the environment use their default values.
+    pub fn from_env() -> Result<Config, RuntimeError> {
+        Config::from_vars(|key| std::env::var(key).ok())
+    }
+
+    /// Load the processor settings from variables looked up by name,
+    /// ignoring empty values.
+    fn from_vars(vars: impl Fn(&str) -> Option<String>) -> Result<Config, RuntimeError> {
+        let env_var = |key: &str| vars(key).filter(|v| !v.is_empty());
+        let mut config = Config::default();
+
+        if let Some(source) = env_var("EVENT_SOURCE") {
+            config.event_source = source.parse()?;
+        }
+
+        if let Some(policy) = env_var("OUT_OF_RANGE_EVENTS") {
+            config.out_of_range_events = policy.parse()?;
+        }
+
+        if let Some(filters) = env_var("LOG_FILTERS") {
+            config.log_filter = LogFilter::from_json(&filters)?;
+        }
+
+        if let Some(days) = env_var("DEFAULT_RETENTION_IN_DAYS") {
+            config.default_retention_in_days = match days.parse() {
+                Ok(days) if is_valid_retention(days) => Some(days),
+                _ => {
+                    return Err(RuntimeError::InvalidConfig(format!(
+                        "invalid retention in days {days}"
+                    )))
+                }
+            };
+        }
+
+        if let Some(prefix) = env_var("LOG_GROUP_PREFIX") {
+            config.log_group_prefix = prefix;
+        }
+
+        if let Some(template) = env_var("LOG_GROUP_TEMPLATE") {
+            config.log_group_template = log_group_template(&template)?;
+        }
+
+        if let Some(strategy) = env_var("LOG_STREAM_STRATEGY") {
+            config.log_stream_strategy = strategy.parse()?;
+        }
+
+        if let Some(resolver) = env_var("FUNCTION_ID_RESOLVER") {
+            config.function_id_resolver = resolver_from_str(&resolver)?;
+        }
+
+        if let Some(seconds) = env_var("FUNCTION_CACHE_TTL_SECONDS") {
+            config.function_cache_ttl = parse_seconds("FUNCTION_CACHE_TTL_SECONDS", &seconds)?;
+        }
+
+        if let Some(seconds) = env_var("FUNCTION_CACHE_NEGATIVE_TTL_SECONDS") {
+            config.function_cache_negative_ttl =
+                parse_seconds("FUNCTION_CACHE_NEGATIVE_TTL_SECONDS", &seconds)?;
+        }
+
+        if let Some(size) = env_var("FUNCTION_CACHE_MAX_SIZE") {
+            config.function_cache_max_size = size.parse().map_err(|_| {
+                RuntimeError::InvalidConfig(format!("invalid function cache max size {size}"))
+            })?;
+        }
+
+        config.retry_queue_url = env_var("RETRY_QUEUE_URL");
+
+        Ok(config)
+    }
+}
+
+/// Parse a number of seconds from an environment variable
+fn parse_seconds(key: &str, value: &str) -> Result<Duration, RuntimeError> {
+    value
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|_| RuntimeError::InvalidConfig(format!("invalid {key} {value}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::LogEntry;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, RuntimeError> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        Config::from_vars(|key| vars.get(key).map(|v| v.to_string()))
+    }
+
+    fn is_invalid(vars: &[(&str, &str)]) -> bool {
+        config(vars).is_err()
+    }
+
+    #[test]
+    fn test_defaults() -> Result<(), RuntimeError> {
+        // empty values are ignored
+        let config = config(&[("EVENT_SOURCE", ""), ("DEFAULT_RETENTION_IN_DAYS", "")])?;
+
+        assert_eq!(EventSource::CloudWatchLogs, config.event_source);
+        assert_eq!(OutOfRangePolicy::default(), config.out_of_range_events);
+        assert_eq!(None, config.default_retention_in_days);
+        assert_eq!(DEFAULT_LOG_GROUP_PREFIX, config.log_group_prefix);
+        assert_eq!(
+            log_group_template(DEFAULT_LOG_GROUP_TEMPLATE)?,
+            config.log_group_template
+        );
+        assert_eq!(LogStreamStrategy::default(), config.log_stream_strategy);
+        assert_eq!(DEFAULT_TTL, config.function_cache_ttl);
+        assert_eq!(DEFAULT_NEGATIVE_TTL, config.function_cache_negative_ttl);
+        assert_eq!(DEFAULT_MAX_SIZE, config.function_cache_max_size);
+        assert_eq!(None, config.retry_queue_url);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_vars() -> Result<(), RuntimeError> {
+        let config = config(&[
+            ("EVENT_SOURCE", "kinesis"),
+            ("OUT_OF_RANGE_EVENTS", "clamp"),
+            (
+                "LOG_FILTERS",
+                r#"[{"action": "exclude", "prefix": "DEBUG"}]"#,
+            ),
+            ("DEFAULT_RETENTION_IN_DAYS", "30"),
+            ("LOG_GROUP_PREFIX", "/custom"),
+            ("LOG_GROUP_TEMPLATE", "{prefix}/{function_id}"),
+            ("LOG_STREAM_STRATEGY", "single:all"),
+            ("FUNCTION_ID_RESOLVER", r"regex:^/aws/lambda/([^/]+)$"),
+            ("FUNCTION_CACHE_TTL_SECONDS", "60"),
+            ("FUNCTION_CACHE_NEGATIVE_TTL_SECONDS", "5"),
+            ("FUNCTION_CACHE_MAX_SIZE", "0"),
+            ("RETRY_QUEUE_URL", "https://queue"),
+        ])?;
+
+        assert_eq!(EventSource::Kinesis, config.event_source);
+        assert_eq!(OutOfRangePolicy::Clamp, config.out_of_range_events);
+        assert!(!config.log_filter.allows(&LogEntry {
+            message: "DEBUG hello".into(),
+            ..Default::default()
+        }));
+        assert_eq!(Some(30), config.default_retention_in_days);
+        assert_eq!("/custom", config.log_group_prefix);
+        assert_eq!(
+            log_group_template("{prefix}/{function_id}")?,
+            config.log_group_template
+        );
+        assert_eq!(
+            LogStreamStrategy::Single("all".into()),
+            config.log_stream_strategy
+        );
+        assert_eq!(
+            Some("function".into()),
+            config.function_id_resolver.resolve("/aws/lambda/function")
+        );
+        assert_eq!(Duration::from_secs(60), config.function_cache_ttl);
+        assert_eq!(Duration::from_secs(5), config.function_cache_negative_ttl);
+        assert_eq!(0, config.function_cache_max_size);
+        assert_eq!(Some("https://queue".into()), config.retry_queue_url);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(is_invalid(&[("EVENT_SOURCE", "sns")]));
+        assert!(is_invalid(&[("OUT_OF_RANGE_EVENTS", "keep")]));
+        assert!(is_invalid(&[("LOG_FILTERS", r#"[{"action": "drop"}]"#)]));
+        assert!(is_invalid(&[("DEFAULT_RETENTION_IN_DAYS", "2")]));
+        assert!(is_invalid(&[("DEFAULT_RETENTION_IN_DAYS", "week")]));
+        assert!(is_invalid(&[("LOG_GROUP_TEMPLATE", "{prefix}/{unknown}")]));
+        assert!(is_invalid(&[("LOG_STREAM_STRATEGY", "hourly")]));
+        assert!(is_invalid(&[(
+            "FUNCTION_ID_RESOLVER",
+            "regex:^/aws/lambda/"
+        )]));
+        assert!(is_invalid(&[("FUNCTION_CACHE_TTL_SECONDS", "-1")]));
+        assert!(is_invalid(&[("FUNCTION_CACHE_NEGATIVE_TTL_SECONDS", "1m")]));
+        assert!(is_invalid(&[("FUNCTION_CACHE_MAX_SIZE", "many")]));
+    }
+}
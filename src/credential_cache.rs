@@ -0,0 +1,175 @@
+use crate::{sts::AssumeRoleOptions, ttl_map::TtlMap};
+use aws_sdk_cloudwatchlogs::Client as CwClient;
+use aws_sdk_s3::Client as S3Client;
+use std::time::{Duration, Instant};
+
+/// Time that clients stay in the cache after their last use
+pub const DEFAULT_CLIENT_TTL: Duration = Duration::from_secs(3600);
+
+/// Number of roles with clients in the cache
+pub const DEFAULT_MAX_CLIENTS: usize = 100;
+
+/// Key of a role session: the role ARN and the assume role options.
+/// Functions that share a role share its session.
+type SessionKey = (String, AssumeRoleOptions);
+
+/// `RoleClients` are the clients created with the credentials of an assumed role
+#[derive(Clone, Debug)]
+pub struct RoleClients {
+    /// Client to deliver the events to CloudWatch Logs
+    pub cloudwatch_logs: CwClient,
+    /// Client to archive the events in S3
+    pub s3: S3Client,
+}
+
+impl RoleClients {
+    /// Create the clients for an assumed role configuration
+    pub fn new(config: &aws_types::SdkConfig) -> RoleClients {
+        RoleClients {
+            cloudwatch_logs: CwClient::new(config),
+            s3: S3Client::new(config),
+        }
+    }
+}
+
+/// `CredentialCache` keeps the clients created with
+/// assumed role credentials, indexed by the role ARN and the assume role options.
+///
+/// The clients refresh their credentials before they expire,
+/// so warm invocations reuse them instead of calling STS for every batch of events.
+/// Clients that are not used for a while are dropped, and the least recently
+/// used ones are evicted when the cache is full, so containers that serve
+/// many roles don't keep all of them.
+#[derive(Debug)]
+pub struct CredentialCache {
+    ttl: Duration,
+    clients: TtlMap<SessionKey, RoleClients>,
+}
+
+impl Default for CredentialCache {
+    fn default() -> Self {
+        CredentialCache::with_limits(DEFAULT_CLIENT_TTL, DEFAULT_MAX_CLIENTS)
+    }
+}
+
+impl CredentialCache {
+    /// Create an empty cache
+    pub fn new() -> CredentialCache {
+        CredentialCache::default()
+    }
+
+    /// Create an empty cache that keeps the clients of up to `max_size` roles,
+    /// for `ttl` after their last use.
+    pub fn with_limits(ttl: Duration, max_size: usize) -> CredentialCache {
+        CredentialCache {
+            ttl,
+            clients: TtlMap::new(max_size),
+        }
+    }
+
+    /// Get the clients of a role
+    pub fn clients(&self, role_arn: &str, options: &AssumeRoleOptions) -> Option<RoleClients> {
+        self.clients_at(role_arn, options, Instant::now())
+    }
+
+    fn clients_at(
+        &self,
+        role_arn: &str,
+        options: &AssumeRoleOptions,
+        now: Instant,
+    ) -> Option<RoleClients> {
+        let key = (role_arn.to_owned(), options.clone());
+        self.clients.get_and_extend(&key, self.ttl, now)
+    }
+
+    /// Remember the clients of a role
+    pub fn insert(&self, role_arn: &str, options: &AssumeRoleOptions, clients: RoleClients) {
+        self.insert_at(role_arn, options, clients, Instant::now())
+    }
+
+    fn insert_at(
+        &self,
+        role_arn: &str,
+        options: &AssumeRoleOptions,
+        clients: RoleClients,
+        now: Instant,
+    ) {
+        let key = (role_arn.to_owned(), options.clone());
+        self.clients.insert(key, clients, self.ttl, now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::get_mock_config;
+
+    #[tokio::test]
+    async fn test_credential_cache() {
+        let clients = RoleClients::new(&get_mock_config().await);
+        let cache = CredentialCache::new();
+
+        let options = AssumeRoleOptions::default();
+        let external = AssumeRoleOptions {
+            external_id: Some("external".into()),
+            ..Default::default()
+        };
+
+        cache.insert("arn", &options, clients);
+
+        assert!(cache.clients("arn", &options).is_some());
+        assert!(cache.clients("arn", &external).is_none());
+        assert!(cache.clients("other", &options).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_credential_cache_expiration() {
+        let clients = RoleClients::new(&get_mock_config().await);
+        let cache = CredentialCache::with_limits(Duration::from_secs(10), 10);
+        let options = AssumeRoleOptions::default();
+        let now = Instant::now();
+
+        cache.insert_at("arn", &options, clients, now);
+
+        // using the clients keeps them in the cache
+        let later = now + Duration::from_secs(8);
+        assert!(cache.clients_at("arn", &options, later).is_some());
+        assert!(cache
+            .clients_at("arn", &options, later + Duration::from_secs(8))
+            .is_some());
+
+        let idle = later + Duration::from_secs(30);
+        assert!(cache.clients_at("arn", &options, idle).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_credential_cache_max_size() {
+        let clients = RoleClients::new(&get_mock_config().await);
+        let cache = CredentialCache::with_limits(Duration::from_secs(10), 2);
+        let options = AssumeRoleOptions::default();
+        let now = Instant::now();
+
+        cache.insert_at("1", &options, clients.clone(), now);
+        cache.insert_at("2", &options, clients.clone(), now);
+        // the first role is used again, so the second one is evicted
+        assert!(cache
+            .clients_at("1", &options, now + Duration::from_secs(1))
+            .is_some());
+        cache.insert_at("3", &options, clients, now + Duration::from_secs(2));
+
+        assert!(cache.clients_at("1", &options, now).is_some());
+        assert!(cache.clients_at("2", &options, now).is_none());
+        assert!(cache.clients_at("3", &options, now).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_credential_cache() {
+        let clients = RoleClients::new(&get_mock_config().await);
+        let cache = CredentialCache::with_limits(Duration::from_secs(10), 0);
+
+        cache.insert("arn", &AssumeRoleOptions::default(), clients);
+        assert!(cache
+            .clients("arn", &AssumeRoleOptions::default())
+            .is_none());
+    }
+}
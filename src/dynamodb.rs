@@ -1,4 +1,16 @@
-use crate::{dynamodb_ext::*, error::RuntimeError, function_info::FunctionInfo};
+use crate::{
+    dynamodb_ext::*,
+    error::RuntimeError,
+    filter::LogFilter,
+    function_cache::FunctionInfoCache,
+    function_info::{
+        parse_log_stream_strategy, validate_archive_bucket, validate_retention_in_days,
+        validate_session_duration, FunctionInfo,
+    },
+    function_store::FunctionInfoStore,
+    naming::log_group_template,
+};
+use async_trait::async_trait;
 use aws_sdk_dynamodb::{model::AttributeValue, Client, Error};
 use std::collections::HashMap;
 
@@ -6,6 +18,7 @@ use std::collections::HashMap;
 pub struct DynamoDBClient {
     inner: Client,
     table: String,
+    cache: FunctionInfoCache,
 }
 
 impl DynamoDBClient {
@@ -17,12 +30,18 @@ impl DynamoDBClient {
         DynamoDBClient {
             inner,
             table: table.into(),
+            cache: FunctionInfoCache::default(),
         }
     }
 
-    /// Fetch the function information from DynamoDB to locate the assume role arn.
-    #[tracing::instrument(skip(self))]
-    pub async fn get_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError> {
+    /// Replace the cache that keeps the function information between invocations.
+    pub fn with_cache(mut self, cache: FunctionInfoCache) -> DynamoDBClient {
+        self.cache = cache;
+        self
+    }
+
+    /// Fetch the function information from DynamoDB, skipping the cache.
+    async fn fetch_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError> {
         let res = self
             .inner
             .get_item()
@@ -38,6 +57,42 @@ impl DynamoDBClient {
     }
 }
 
+#[async_trait]
+impl FunctionInfoStore for DynamoDBClient {
+    /// Fetch the function information from DynamoDB.
+    /// Functions are served from the cache while their entries are fresh.
+    #[tracing::instrument(skip(self))]
+    async fn get_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError> {
+        if let Some(res) = self.cache.get(id) {
+            return res;
+        }
+
+        let res = self.fetch_function_info(id).await;
+        self.cache.insert(id, &res);
+        res
+    }
+}
+
+/// Read a numeric field that must be an integer.
+/// Values that are not numbers, like numbers stored as strings,
+/// and numbers with decimals or outside of the i32 range are rejected
+/// instead of being ignored or truncated.
+fn get_integer(
+    item: &HashMap<String, AttributeValue>,
+    field: &str,
+) -> Result<Option<i32>, RuntimeError> {
+    if matches!(item.get(field), None | Some(AttributeValue::Null(_))) {
+        return Ok(None);
+    }
+
+    match item.get_n(field) {
+        Some(n) if n.fract() == 0.0 && n >= f64::from(i32::MIN) && n <= f64::from(i32::MAX) => {
+            Ok(Some(n as i32))
+        }
+        _ => Err(RuntimeError::InvalidField(field.into())),
+    }
+}
+
 impl TryFrom<HashMap<String, AttributeValue>> for FunctionInfo {
     type Error = RuntimeError;
 
@@ -56,6 +111,35 @@ impl TryFrom<HashMap<String, AttributeValue>> for FunctionInfo {
                 .ok_or_else(|| {
                     RuntimeError::MissingField("cloudwatch_logs_assume_role_arn".into())
                 })?,
+            app_id: value.get_s("app_id"),
+            branch: value.get_s("branch"),
+            log_group_template: value
+                .get_s("log_group_template")
+                .map(|template| log_group_template(&template))
+                .transpose()?,
+            log_stream_strategy: value
+                .get_s("log_stream_strategy")
+                .map(|strategy| parse_log_stream_strategy(&strategy))
+                .transpose()?,
+            log_filter: value
+                .get_s("log_filters")
+                .map(|json| LogFilter::from_json(&json))
+                .transpose()?,
+            retention_in_days: get_integer(&value, "retention_in_days")?
+                .map(validate_retention_in_days)
+                .transpose()?,
+            kms_key_id: value.get_s("kms_key_id"),
+            tags: value.get_m("tags").unwrap_or_default(),
+            external_id: value.get_s("external_id"),
+            session_duration_seconds: get_integer(&value, "session_duration_seconds")?
+                .map(validate_session_duration)
+                .transpose()?,
+            source_identity: value.get_s("source_identity"),
+            tag_session: value.get_bool("tag_session").unwrap_or_default(),
+            archive_bucket: value
+                .get_s("archive_bucket")
+                .map(validate_archive_bucket)
+                .transpose()?,
         })
     }
 }
@@ -63,7 +147,7 @@ impl TryFrom<HashMap<String, AttributeValue>> for FunctionInfo {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_util::*;
+    use crate::{naming::LogStreamStrategy, test_util::*};
     use aws_sdk_dynamodb::{Client, Config};
     use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
     use aws_smithy_http::body::SdkBody;
@@ -79,7 +163,7 @@ mod test {
                 .unwrap(),
             http::Response::builder()
                 .status(200)
-                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "app-id-1-branch-2"}, "cloudwatch_logs_assume_role_arn": {"S": "arn"}}}"#))
+                .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "app-id-1-branch-2"}, "cloudwatch_logs_assume_role_arn": {"S": "arn"}, "kms_key_id": {"S": "key"}, "tags": {"M": {"team": {"S": "web"}}}}}"#))
                 .unwrap(),
         )]);
         let config = Config::new(&get_mock_config().await);
@@ -88,6 +172,7 @@ mod test {
         let store = DynamoDBClient {
             inner,
             table: "test".to_string(),
+            cache: FunctionInfoCache::default(),
         };
 
         // WHEN getting an item
@@ -97,9 +182,257 @@ mod test {
         assert_eq!("1", function.id);
         assert_eq!("app-id-1-branch-2", function.name);
         assert_eq!("arn", function.cloudwatch_logs_assume_role_arn);
+        assert_eq!(None, function.log_filter);
+        assert_eq!(None, function.retention_in_days);
+        assert_eq!(Some("key".into()), function.kms_key_id);
+        assert_eq!(Some(&"web".to_string()), function.tags.get("team"));
 
         // AND the request matches the expected request
-        conn.assert_requests_match(&vec![]);
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_function_info_from_cache() -> Result<(), RuntimeError> {
+        // GIVEN a DynamoDBClient with one item, one missing item and one invalid item
+        let conn = TestConnection::new(vec![
+            (
+                get_request_builder("dynamodb")
+                    .header("content-type", "application/x-amz-json-1.0")
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "1"}}}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Item": {"id": {"S": "1"}, "name": {"S": "app-id-1-branch-2"}, "cloudwatch_logs_assume_role_arn": {"S": "arn"}}}"#))
+                    .unwrap(),
+            ),
+            (
+                get_request_builder("dynamodb")
+                    .header("content-type", "application/x-amz-json-1.0")
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "2"}}}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from("{}"))
+                    .unwrap(),
+            ),
+                    (
+                get_request_builder("dynamodb")
+                    .header("content-type", "application/x-amz-json-1.0")
+                    .header("x-amz-target", "DynamoDB_20120810.GetItem")
+                    .body(SdkBody::from(r#"{"TableName": "test", "Key": {"id": {"S": "3"}}}"#))
+                    .unwrap(),
+                http::Response::builder()
+                    .status(200)
+                    .body(SdkBody::from(r#"{"Item": {"id": {"S": "3"}}}"#))
+                    .unwrap(),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let inner = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+
+        let store = DynamoDBClient {
+            inner,
+            table: "test".to_string(),
+            cache: FunctionInfoCache::default(),
+        };
+
+        // WHEN getting each item twice
+        for _ in 0..2 {
+            assert_eq!("1", store.get_function_info("1").await?.id);
+            assert!(matches!(
+                store.get_function_info("2").await,
+                Err(RuntimeError::MissingFunction(_))
+            ));
+            assert!(matches!(
+                store.get_function_info("3").await,
+                Err(RuntimeError::MissingField(_))
+            ));
+        }
+
+        // THEN DynamoDB only receives one request per item
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_info_with_log_filters() -> Result<(), RuntimeError> {
+        let item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("1".into())),
+            (
+                "name".to_string(),
+                AttributeValue::S("app-id-1-branch-2".into()),
+            ),
+            (
+                "cloudwatch_logs_assume_role_arn".to_string(),
+                AttributeValue::S("arn".into()),
+            ),
+            (
+                "log_filters".to_string(),
+                AttributeValue::S(r#"[{"action": "exclude", "prefix": "START"}]"#.into()),
+            ),
+        ]);
+
+        let function = FunctionInfo::try_from(item)?;
+        let filter = function.log_filter.expect("missing log filter");
+        assert!(!filter.allows(&crate::event::LogEntry {
+            message: "START RequestId: 1".into(),
+            ..Default::default()
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_info_with_retention() -> Result<(), RuntimeError> {
+        let mut item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("1".into())),
+            (
+                "name".to_string(),
+                AttributeValue::S("app-id-1-branch-2".into()),
+            ),
+            (
+                "cloudwatch_logs_assume_role_arn".to_string(),
+                AttributeValue::S("arn".into()),
+            ),
+            (
+                "retention_in_days".to_string(),
+                AttributeValue::N("30".into()),
+            ),
+        ]);
+
+        let function = FunctionInfo::try_from(item.clone())?;
+        assert_eq!(Some(30), function.retention_in_days);
+
+        item.insert(
+            "retention_in_days".to_string(),
+            AttributeValue::N("31".into()),
+        );
+        assert!(matches!(
+            FunctionInfo::try_from(item.clone()),
+            Err(RuntimeError::InvalidField(_))
+        ));
+
+        for days in [
+            AttributeValue::N("30.5".into()),
+            AttributeValue::N("1e12".into()),
+            AttributeValue::S("30".into()),
+        ] {
+            item.insert("retention_in_days".to_string(), days);
+            assert!(matches!(
+                FunctionInfo::try_from(item.clone()),
+                Err(RuntimeError::InvalidField(field)) if field == "retention_in_days"
+            ));
+        }
+
+        item.insert("retention_in_days".to_string(), AttributeValue::Null(true));
+        assert_eq!(None, FunctionInfo::try_from(item)?.retention_in_days);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_info_with_log_group_template() -> Result<(), RuntimeError> {
+        let mut item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("1".into())),
+            (
+                "name".to_string(),
+                AttributeValue::S("app-id-1-branch-2".into()),
+            ),
+            (
+                "cloudwatch_logs_assume_role_arn".to_string(),
+                AttributeValue::S("arn".into()),
+            ),
+            ("app_id".to_string(), AttributeValue::S("app-id-1".into())),
+            ("branch".to_string(), AttributeValue::S("branch-2".into())),
+            (
+                "log_group_template".to_string(),
+                AttributeValue::S("/{app_id}/{branch}".into()),
+            ),
+            (
+                "log_stream_strategy".to_string(),
+                AttributeValue::S("single:app".into()),
+            ),
+        ]);
+
+        let function = FunctionInfo::try_from(item.clone())?;
+        assert_eq!(Some("app-id-1".into()), function.app_id);
+        assert_eq!(Some("branch-2".into()), function.branch);
+        assert_eq!(
+            Some("/{app_id}/{branch}".into()),
+            function.log_group_template.map(|t| t.to_string())
+        );
+        assert_eq!(
+            Some(LogStreamStrategy::Single("app".into())),
+            function.log_stream_strategy
+        );
+
+        item.insert(
+            "log_group_template".to_string(),
+            AttributeValue::S("/{unknown}".into()),
+        );
+        assert!(matches!(
+            FunctionInfo::try_from(item),
+            Err(RuntimeError::InvalidTemplate(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_info_with_assume_role_options() -> Result<(), RuntimeError> {
+        let mut item = HashMap::from([
+            ("id".to_string(), AttributeValue::S("1".into())),
+            (
+                "name".to_string(),
+                AttributeValue::S("app-id-1-branch-2".into()),
+            ),
+            (
+                "cloudwatch_logs_assume_role_arn".to_string(),
+                AttributeValue::S("arn".into()),
+            ),
+            (
+                "external_id".to_string(),
+                AttributeValue::S("external".into()),
+            ),
+            (
+                "session_duration_seconds".to_string(),
+                AttributeValue::N("3600".into()),
+            ),
+            (
+                "source_identity".to_string(),
+                AttributeValue::S("processor".into()),
+            ),
+            ("tag_session".to_string(), AttributeValue::Bool(true)),
+        ]);
+
+        let function = FunctionInfo::try_from(item.clone())?;
+        assert_eq!(Some("external".into()), function.external_id);
+        assert_eq!(Some(3600), function.session_duration_seconds);
+        assert_eq!(Some("processor".into()), function.source_identity);
+        assert!(function.tag_session);
+
+        item.insert(
+            "session_duration_seconds".to_string(),
+            AttributeValue::N("60".into()),
+        );
+        assert!(matches!(
+            FunctionInfo::try_from(item.clone()),
+            Err(RuntimeError::InvalidField(_))
+        ));
+
+        item.insert(
+            "session_duration_seconds".to_string(),
+            AttributeValue::N("3600.5".into()),
+        );
+        assert!(matches!(
+            FunctionInfo::try_from(item),
+            Err(RuntimeError::InvalidField(_))
+        ));
 
         Ok(())
     }
@@ -11,6 +11,8 @@ use std::collections::HashMap;
 pub trait AttributeValuesExt {
     fn get_s(&self, key: &str) -> Option<String>;
     fn get_n(&self, key: &str) -> Option<f64>;
+    fn get_m(&self, key: &str) -> Option<HashMap<String, String>>;
+    fn get_bool(&self, key: &str) -> Option<bool>;
 }
 
 impl AttributeValuesExt for HashMap<String, AttributeValue> {
@@ -45,6 +47,48 @@ impl AttributeValuesExt for HashMap<String, AttributeValue> {
     fn get_n(&self, key: &str) -> Option<f64> {
         self.get(key)?.as_n().ok()?.parse::<f64>().ok()
     }
+
+    /// Return a map of strings from a key
+    ///
+    /// E.g. if you run `get_m("tags")` on a DynamoDB item structured like this,
+    /// you will retrieve the map `{"team": "web"}`.
+    /// Values that are not strings are ignored.
+    ///
+    /// ```json
+    /// {
+    ///   "tags": {
+    ///     "M": {
+    ///       "team": {
+    ///         "S": "web"
+    ///       }
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    fn get_m(&self, key: &str) -> Option<HashMap<String, String>> {
+        let map = self.get(key)?.as_m().ok()?;
+        Some(
+            map.iter()
+                .filter_map(|(k, v)| Some((k.clone(), v.as_s().ok()?.clone())))
+                .collect(),
+        )
+    }
+
+    /// Return a boolean from a key
+    ///
+    /// E.g. if you run `get_bool("enabled")` on a DynamoDB item structured like this,
+    /// you will retrieve the value `true`.
+    ///
+    /// ```json
+    /// {
+    ///   "enabled": {
+    ///     "BOOL": true
+    ///   }
+    /// }
+    /// ```
+    fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)?.as_bool().ok().copied()
+    }
 }
 
 #[cfg(test)]
@@ -82,4 +126,41 @@ mod tests {
 
         assert_eq!(item.get_n("foo"), None);
     }
+
+    #[test]
+    fn attributevalue_get_m() {
+        let mut item = HashMap::new();
+        item.insert(
+            "tags".to_owned(),
+            AttributeValue::M(HashMap::from([
+                ("team".to_owned(), AttributeValue::S("web".to_owned())),
+                ("count".to_owned(), AttributeValue::N("1".to_owned())),
+            ])),
+        );
+
+        assert_eq!(
+            item.get_m("tags"),
+            Some(HashMap::from([("team".to_owned(), "web".to_owned())]))
+        );
+    }
+
+    #[test]
+    fn attributevalue_get_m_missing() {
+        let mut item = HashMap::new();
+        item.insert("tags".to_owned(), AttributeValue::S("foo".to_owned()));
+
+        assert_eq!(item.get_m("foo"), None);
+        assert_eq!(item.get_m("tags"), None);
+    }
+
+    #[test]
+    fn attributevalue_get_bool() {
+        let mut item = HashMap::new();
+        item.insert("enabled".to_owned(), AttributeValue::Bool(true));
+        item.insert("name".to_owned(), AttributeValue::S("true".to_owned()));
+
+        assert_eq!(item.get_bool("enabled"), Some(true));
+        assert_eq!(item.get_bool("name"), None);
+        assert_eq!(item.get_bool("foo"), None);
+    }
 }
@@ -6,6 +6,18 @@ pub enum RuntimeError {
     /// Error returned when we cannot find the function info in DynamoDB
     #[error("unable to find function information for log group {0}")]
     MissingFunction(String),
+    /// Error returned when the events could not be delivered to their destination
+    #[error("failed to deliver events: {0}")]
+    DeliveryFailed(String),
+    /// Error returned when a record doesn't have a valid CloudWatch Logs payload
+    #[error("invalid log data payload: {0}")]
+    InvalidPayload(String),
+    /// Error returned when CloudWatch sends a message type that we don't know
+    #[error("unknown message type {0}")]
+    UnknownMessageType(String),
+    /// Error returned when the function id cannot be extracted from the log group name
+    #[error("unable to extract a function id from log group {0}")]
+    UnresolvedFunctionId(String),
     /// Error returned if we cannot assume a specific role
     #[error("failed to assume role")]
     AssumeRoleFailure(#[from] aws_sdk_sts::Error),
@@ -18,7 +30,69 @@ pub enum RuntimeError {
     /// Error returned if the function info item in DynamoDB is missing an expected field
     #[error("missing item field {0}")]
     MissingField(String),
+    /// Error returned if the function info item in DynamoDB has a field with an invalid value
+    #[error("invalid item field {0}")]
+    InvalidField(String),
+    /// Error returned by the S3 API
+    #[error("unexpected s3 error")]
+    S3(#[from] aws_sdk_s3::Error),
+    /// Error returned by the SQS API
+    #[error("unexpected sqs error")]
+    Sqs(#[from] aws_sdk_sqs::Error),
     /// Error retuned by the DynamoDB API
     #[error("unexpected dynamodb error")]
     DynamoDB(#[from] aws_sdk_dynamodb::Error),
+    /// Error returned if the processor settings are not valid
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    /// Error returned if a name template is not valid, or it cannot be rendered
+    #[error("invalid template {0}")]
+    InvalidTemplate(String),
+    /// Error returned if a log group name doesn't follow the CloudWatch naming rules
+    #[error("invalid log group name: {0}")]
+    InvalidLogGroupName(String),
+    /// Error returned if a log stream name doesn't follow the CloudWatch naming rules
+    #[error("invalid log stream name: {0}")]
+    InvalidLogStreamName(String),
+}
+
+impl RuntimeError {
+    /// Check if the error is transient, so delivering the events again may succeed,
+    /// like throttling, service errors, or STS failures.
+    /// Permanent errors, like missing functions or invalid settings,
+    /// fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        use aws_sdk_cloudwatchlogs::Error as CwError;
+        use aws_sdk_sts::Error as StsError;
+
+        match self {
+            RuntimeError::AssumeRoleFailure(err) => !matches!(
+                err,
+                StsError::MalformedPolicyDocumentException(_)
+                    | StsError::PackedPolicyTooLargeException(_)
+                    | StsError::RegionDisabledException(_)
+            ),
+            RuntimeError::CloudWatchLogs(err) => !matches!(
+                err,
+                CwError::InvalidOperationException(_)
+                    | CwError::InvalidParameterException(_)
+                    | CwError::MalformedQueryException(_)
+            ),
+            RuntimeError::MissingCredentials
+            | RuntimeError::DeliveryFailed(_)
+            | RuntimeError::S3(_)
+            | RuntimeError::Sqs(_)
+            | RuntimeError::DynamoDB(_) => true,
+            RuntimeError::MissingFunction(_)
+            | RuntimeError::InvalidPayload(_)
+            | RuntimeError::UnknownMessageType(_)
+            | RuntimeError::UnresolvedFunctionId(_)
+            | RuntimeError::MissingField(_)
+            | RuntimeError::InvalidField(_)
+            | RuntimeError::InvalidConfig(_)
+            | RuntimeError::InvalidTemplate(_)
+            | RuntimeError::InvalidLogGroupName(_)
+            | RuntimeError::InvalidLogStreamName(_) => false,
+        }
+    }
 }
@@ -1,8 +1,9 @@
+use crate::error::RuntimeError;
 use serde::{
     de::{Error, MapAccess, Visitor},
-    Deserialize, Deserializer,
+    Deserialize, Deserializer, Serialize,
 };
-use std::{fmt, io::BufReader};
+use std::{fmt, io::BufReader, str::FromStr};
 
 /// `LogsEvent` represents the raw event sent by CloudWatch
 #[derive(Clone, Debug, Default, Deserialize, PartialEq)]
@@ -20,7 +21,7 @@ pub struct AwsLogs {
 }
 
 /// `LogData` represents the logs group event information
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogData {
     /// Owner of the log event
@@ -37,8 +38,62 @@ pub struct LogData {
     pub log_events: Vec<LogEntry>,
 }
 
+impl LogData {
+    /// Decode the base64 gzipped JSON payload that CloudWatch sends
+    /// to the subscription destinations
+    pub fn decode(payload: &str) -> Result<LogData, RuntimeError> {
+        let bytes =
+            base64::decode(payload).map_err(|e| RuntimeError::InvalidPayload(e.to_string()))?;
+
+        let bytes = flate2::read::GzDecoder::new(&bytes[..]);
+        let mut de = serde_json::Deserializer::from_reader(BufReader::new(bytes));
+        LogData::deserialize(&mut de).map_err(|e| RuntimeError::InvalidPayload(e.to_string()))
+    }
+
+    /// Encode the log data as base64 gzipped JSON, like CloudWatch does
+    pub fn encode(&self) -> String {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, self).expect("failed to serialize log data");
+        let bytes = encoder.finish().expect("failed to compress log data");
+        base64::encode(bytes)
+    }
+
+    /// Copy of the log data from the same source with other events
+    pub fn with_events(&self, log_events: Vec<LogEntry>) -> LogData {
+        LogData {
+            owner: self.owner.clone(),
+            log_group: self.log_group.clone(),
+            log_stream: self.log_stream.clone(),
+            subscription_filters: self.subscription_filters.clone(),
+            message_type: self.message_type.clone(),
+            log_events,
+        }
+    }
+}
+
+/// `MessageType` is the kind of payload that CloudWatch sends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    /// Batch of log events from a log group
+    Data,
+    /// Message that CloudWatch sends to check that the destination is reachable
+    Control,
+}
+
+impl FromStr for MessageType {
+    type Err = RuntimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "DATA_MESSAGE" => Ok(MessageType::Data),
+            "CONTROL_MESSAGE" => Ok(MessageType::Control),
+            _ => Err(RuntimeError::UnknownMessageType(s.into())),
+        }
+    }
+}
+
 /// `LogEntry` represents a log entry from cloudwatch logs
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct LogEntry {
     /// Unique id for the entry
     pub id: String,
@@ -70,14 +125,8 @@ impl<'de> Deserialize<'de> for AwsLogs {
                 while let Some(key) = map.next_key()? {
                     match key {
                         "data" => {
-                            let bytes = map.next_value::<String>().and_then(|string| {
-                                base64::decode(&string).map_err(Error::custom)
-                            })?;
-
-                            let bytes = flate2::read::GzDecoder::new(&bytes[..]);
-                            let mut de =
-                                serde_json::Deserializer::from_reader(BufReader::new(bytes));
-                            data = Some(LogData::deserialize(&mut de).map_err(Error::custom)?);
+                            let payload = map.next_value::<String>()?;
+                            data = Some(LogData::decode(&payload).map_err(Error::custom)?);
                         }
                         _ => return Err(Error::unknown_field(key, FIELDS)),
                     }
@@ -126,4 +175,42 @@ mod test {
         assert_eq!(1552518348220, data.log_events[0].timestamp);
         assert_eq!("REPORT RequestId: 6234bffe-149a-b642-81ff-2e8e376d8aff\tDuration: 46.84 ms\tBilled Duration: 47 ms \tMemory Size: 192 MB\tMax Memory Used: 72 MB\t\n", data.log_events[0].message);
     }
+
+    #[test]
+    fn test_encode_log_data() {
+        let data = LogData {
+            log_group: "/aws/lambda/function".into(),
+            message_type: "DATA_MESSAGE".into(),
+            log_events: vec![LogEntry {
+                id: "1".into(),
+                timestamp: 1552518348220,
+                message: "hello".into(),
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(data, LogData::decode(&data.encode()).unwrap());
+    }
+
+    #[test]
+    fn test_decode_invalid_payload() {
+        assert!(matches!(
+            LogData::decode("not base64!"),
+            Err(RuntimeError::InvalidPayload(_))
+        ));
+        assert!(matches!(
+            LogData::decode("aGVsbG8="),
+            Err(RuntimeError::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_message_type() {
+        assert_eq!(MessageType::Data, "DATA_MESSAGE".parse().unwrap());
+        assert_eq!(MessageType::Control, "CONTROL_MESSAGE".parse().unwrap());
+        assert!(matches!(
+            "OTHER".parse::<MessageType>(),
+            Err(RuntimeError::UnknownMessageType(_))
+        ));
+    }
 }
@@ -0,0 +1,285 @@
+use crate::{error::RuntimeError, event::LogEntry};
+use regex::Regex;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Log level detected in a log message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Level {
+    /// TRACE messages
+    Trace,
+    /// DEBUG messages
+    Debug,
+    /// INFO messages
+    Info,
+    /// WARN and WARNING messages
+    Warn,
+    /// ERROR messages
+    Error,
+    /// FATAL and CRITICAL messages
+    Fatal,
+}
+
+impl FromStr for Level {
+    type Err = RuntimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "TRACE" => Ok(Level::Trace),
+            "DEBUG" => Ok(Level::Debug),
+            "INFO" => Ok(Level::Info),
+            "WARN" | "WARNING" => Ok(Level::Warn),
+            "ERROR" => Ok(Level::Error),
+            "FATAL" | "CRITICAL" => Ok(Level::Fatal),
+            _ => Err(RuntimeError::InvalidConfig(format!(
+                "unknown log level {s}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for Level {
+    type Error = RuntimeError;
+
+    fn try_from(value: String) -> Result<Self, RuntimeError> {
+        value.parse()
+    }
+}
+
+impl Level {
+    /// Level written in a log line, as the runtimes write it, in uppercase
+    fn from_field(field: &str) -> Option<Level> {
+        match field {
+            "TRACE" => Some(Level::Trace),
+            "DEBUG" => Some(Level::Debug),
+            "INFO" => Some(Level::Info),
+            "WARN" | "WARNING" => Some(Level::Warn),
+            "ERROR" => Some(Level::Error),
+            "FATAL" | "CRITICAL" => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+
+    /// Detect the level of a log message.
+    ///
+    /// Only the level field of the Lambda runtime formats is inspected:
+    /// the third tab separated field, like `2022-06-09T10:00:00.000Z\t<request id>\tINFO\tmessage`,
+    /// or a prefix like `[ERROR] message` or `ERROR: message`.
+    /// Levels elsewhere in the message, or in lowercase, are not detected.
+    pub fn detect(message: &str) -> Option<Level> {
+        if let Some(level) = message.split('\t').nth(2).and_then(Level::from_field) {
+            return Some(level);
+        }
+
+        let prefix = message.split_whitespace().next()?;
+        prefix
+            .strip_prefix('[')
+            .and_then(|p| p.strip_suffix(']'))
+            .or_else(|| prefix.strip_suffix(':'))
+            .and_then(Level::from_field)
+    }
+}
+
+/// What to do with the events that match a rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Only deliver events that match at least one include rule
+    Include,
+    /// Never deliver events that match the rule
+    Exclude,
+}
+
+/// Condition that an event must meet to match a rule
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Matcher {
+    /// The message contains the text
+    Substring(String),
+    /// The message starts with the text
+    Prefix(String),
+    /// The message matches the regular expression
+    #[serde(with = "serde_regex")]
+    Regex(Regex),
+    /// The level detected in the message is the given level
+    Level(Level),
+}
+
+impl Matcher {
+    /// Check if a log message meets the condition
+    pub fn matches(&self, message: &str) -> bool {
+        match self {
+            Matcher::Substring(text) => message.contains(text.as_str()),
+            Matcher::Prefix(text) => message.starts_with(text.as_str()),
+            Matcher::Regex(regex) => regex.is_match(message),
+            Matcher::Level(level) => Level::detect(message) == Some(*level),
+        }
+    }
+}
+
+impl PartialEq for Matcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Matcher::Substring(a), Matcher::Substring(b)) => a == b,
+            (Matcher::Prefix(a), Matcher::Prefix(b)) => a == b,
+            (Matcher::Regex(a), Matcher::Regex(b)) => a.as_str() == b.as_str(),
+            (Matcher::Level(a), Matcher::Level(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// `FilterRule` decides if the events that match a condition are delivered
+///
+/// Rules are written in JSON, like `{"action": "exclude", "substring": "Listening on port"}`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct FilterRule {
+    /// What to do with the events that match the rule
+    pub action: FilterAction,
+    /// Condition that the events must meet
+    #[serde(flatten)]
+    pub matcher: Matcher,
+}
+
+/// `LogFilter` decides which events are delivered to the customer account
+///
+/// An event is delivered when it doesn't match any exclude rule and,
+/// if there are include rules, it matches at least one of them.
+/// Empty messages are never delivered because CloudWatch rejects them.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct LogFilter {
+    rules: Vec<FilterRule>,
+}
+
+impl Default for LogFilter {
+    /// Skip the messages that web servers print when they start
+    fn default() -> Self {
+        LogFilter {
+            rules: vec![FilterRule {
+                action: FilterAction::Exclude,
+                matcher: Matcher::Substring("Listening on port".into()),
+            }],
+        }
+    }
+}
+
+impl LogFilter {
+    /// Create a filter with a list of rules
+    pub fn new(rules: Vec<FilterRule>) -> LogFilter {
+        LogFilter { rules }
+    }
+
+    /// Load the filter rules from a JSON array
+    pub fn from_json(json: &str) -> Result<LogFilter, RuntimeError> {
+        serde_json::from_str(json)
+            .map_err(|e| RuntimeError::InvalidConfig(format!("invalid log filters: {e}")))
+    }
+
+    /// Check if an event must be delivered
+    pub fn allows(&self, event: &LogEntry) -> bool {
+        if event.message.is_empty() {
+            return false;
+        }
+
+        let mut has_includes = false;
+        let mut included = false;
+
+        for rule in &self.rules {
+            let matches = rule.matcher.matches(&event.message);
+            match rule.action {
+                FilterAction::Exclude if matches => return false,
+                FilterAction::Exclude => {}
+                FilterAction::Include => {
+                    has_includes = true;
+                    included |= matches;
+                }
+            }
+        }
+
+        !has_includes || included
+    }
+}
+
+/// Deserialize regular expressions from strings
+mod serde_regex {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map_err(Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(message: &str) -> LogEntry {
+        LogEntry {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let filter = LogFilter::default();
+        assert!(!filter.allows(&entry("")));
+        assert!(!filter.allows(&entry("Listening on port 3000")));
+        assert!(filter.allows(&entry("GET /homepage")));
+    }
+
+    #[test]
+    fn test_filter_from_json() {
+        let filter = LogFilter::from_json(
+            r#"[
+                {"action": "exclude", "prefix": "START RequestId"},
+                {"action": "exclude", "regex": "^(END|REPORT) RequestId"},
+                {"action": "exclude", "level": "debug"},
+                {"action": "include", "substring": "GET"},
+                {"action": "include", "level": "ERROR"}
+            ]"#,
+        )
+        .expect("failed to load filters");
+
+        assert!(!filter.allows(&entry("START RequestId: 1234")));
+        assert!(!filter.allows(&entry("REPORT RequestId: 1234")));
+        assert!(!filter.allows(&entry("2022-06-09T10:00:00.000Z\t1234\tDEBUG\tGET /")));
+        assert!(filter.allows(&entry("2022-06-09T10:00:00.000Z\t1234\tINFO\tGET /")));
+        assert!(filter.allows(&entry("[ERROR] something went wrong")));
+        assert!(!filter.allows(&entry("POST /form")));
+    }
+
+    #[test]
+    fn test_filter_from_invalid_json() {
+        assert!(LogFilter::from_json(r#"[{"action": "exclude", "regex": "("}]"#).is_err());
+        assert!(LogFilter::from_json(r#"[{"action": "exclude", "level": "loud"}]"#).is_err());
+        assert!(LogFilter::from_json(r#"[{"action": "drop", "prefix": "a"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_detect_level() {
+        assert_eq!(
+            Some(Level::Info),
+            Level::detect("2022-06-09T10:00:00.000Z\t1234\tINFO\thello")
+        );
+        assert_eq!(Some(Level::Warn), Level::detect("[WARNING] careful"));
+        assert_eq!(Some(Level::Error), Level::detect("ERROR: failed"));
+        assert_eq!(None, Level::detect("GET /homepage"));
+
+        assert_eq!(None, Level::detect("GET /info page"));
+        assert_eq!(None, Level::detect("User error: bad input"));
+        assert_eq!(None, Level::detect("[info] lowercase"));
+        assert_eq!(None, Level::detect("request failed with ERROR"));
+        assert_eq!(
+            None,
+            Level::detect("2022-06-09T10:00:00.000Z\t1234\tmessage\tERROR")
+        );
+    }
+}
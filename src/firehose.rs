@@ -0,0 +1,295 @@
+use crate::{
+    deliver_log_data,
+    error::RuntimeError,
+    event::{LogData, LogEntry, MessageType},
+    function_store::FunctionInfoStore,
+    report::InvocationReport,
+    Config, CredentialCache, StreamCache,
+};
+use aws_sdk_sts::Client as StsClient;
+use lambda_runtime::LambdaEvent;
+use serde::{Deserialize, Serialize};
+
+/// `FirehoseEvent` is the batch of records that Kinesis Data Firehose sends
+/// to a record transformation function
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FirehoseEvent {
+    /// Id of the transformation invocation
+    pub invocation_id: String,
+    /// ARN of the delivery stream
+    pub delivery_stream_arn: String,
+    /// Records to transform
+    pub records: Vec<FirehoseEventRecord>,
+}
+
+/// `FirehoseEventRecord` is a record in a Firehose batch
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FirehoseEventRecord {
+    /// Id of the record, that must be returned in the response
+    pub record_id: String,
+    /// Base64 gzipped CloudWatch Logs payload
+    pub data: String,
+}
+
+/// `FirehoseResponse` has the result of the transformation of each record
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FirehoseResponse {
+    /// Transformed records, in the same order as the event records
+    pub records: Vec<FirehoseResponseRecord>,
+}
+
+/// `FirehoseResponseRecord` is the transformation result of a record
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirehoseResponseRecord {
+    /// Id of the record in the event
+    pub record_id: String,
+    /// What Firehose does with the record
+    pub result: FirehoseResult,
+    /// Record data that Firehose delivers to its destination
+    pub data: String,
+}
+
+/// `FirehoseResult` tells Firehose what to do with a transformed record
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum FirehoseResult {
+    /// The record was delivered, Firehose sends it to its destination
+    Ok,
+    /// The record doesn't have events to deliver, Firehose discards it
+    Dropped,
+    /// The record could not be delivered, Firehose sends it to its error output
+    ProcessingFailed,
+}
+
+/// Encode the events as base64 newline delimited JSON
+fn encode_events(events: &[LogEntry]) -> String {
+    let mut bytes = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut bytes, event).expect("failed to serialize log event");
+        bytes.push(b'\n');
+    }
+    base64::encode(bytes)
+}
+
+/// Data of a transformed record, with the events that were delivered,
+/// or None when no events remain and the record must be dropped
+fn transformed_data(data: &LogData, report: &InvocationReport) -> Option<String> {
+    let events = report.delivered(&data.log_events);
+    if events.is_empty() {
+        None
+    } else {
+        Some(encode_events(&events))
+    }
+}
+
+/// Deliver the events of a record, and return the transformed data,
+/// or None when Firehose must drop the record
+async fn transform_record<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    request_id: &str,
+    record: &FirehoseEventRecord,
+) -> Result<Option<String>, RuntimeError> {
+    let data = LogData::decode(&record.data)?;
+
+    if data.message_type.parse::<MessageType>()? == MessageType::Control {
+        return Ok(None);
+    }
+
+    let report = deliver_log_data(
+        sts_client,
+        credentials,
+        function_store,
+        config,
+        cache,
+        request_id,
+        &data,
+        &[],
+    )
+    .await;
+
+    if let Some(err) = report.error() {
+        return Err(RuntimeError::DeliveryFailed(err.into()));
+    }
+
+    Ok(transformed_data(&data, &report))
+}
+
+/// `handle_firehose_events` is the Lambda function entry point
+/// when the processor runs as a Firehose record transformation.
+///
+/// The events of each record are delivered like subscription events.
+/// Delivered records are returned as newline delimited JSON with the events
+/// that were delivered. Control messages and records without delivered events,
+/// because they were filtered, dropped or rejected, are dropped.
+/// Records that could not be delivered are marked as failed, with their original data.
+#[tracing::instrument(skip(sts_client, credentials, function_store, config, cache, event))]
+pub async fn handle_firehose_events<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    event: LambdaEvent<FirehoseEvent>,
+) -> Result<FirehoseResponse, RuntimeError> {
+    let request_id = event.context.request_id;
+    let mut response = FirehoseResponse::default();
+
+    for record in event.payload.records {
+        let res = transform_record(
+            sts_client,
+            credentials,
+            function_store,
+            config,
+            cache,
+            &request_id,
+            &record,
+        )
+        .await;
+
+        let (result, data) = match res {
+            Ok(Some(data)) => (FirehoseResult::Ok, data),
+            Ok(None) => (FirehoseResult::Dropped, record.data),
+            Err(err) => {
+                tracing::error!(record_id = %record.record_id, "failed to deliver record: {err}");
+                (FirehoseResult::ProcessingFailed, record.data)
+            }
+        };
+
+        response.records.push(FirehoseResponseRecord {
+            record_id: record.record_id,
+            result,
+            data,
+        });
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        function_store::MemoryStore,
+        report::{DeliveryReport, DestinationReport},
+        test_util::*,
+    };
+    use lambda_runtime::Context;
+
+    #[test]
+    fn test_deserialize_firehose_event() {
+        let json = r#"{"invocationId": "invocation", "deliveryStreamArn": "arn",
+            "region": "us-east-1", "records": [{"recordId": "1",
+            "approximateArrivalTimestamp": 1510772160000, "data": "SGVsbG8="}]}"#;
+
+        let event: FirehoseEvent = serde_json::from_str(json).expect("failed to deserialize");
+        assert_eq!(1, event.records.len());
+        assert_eq!("1", event.records[0].record_id);
+    }
+
+    #[test]
+    fn test_serialize_firehose_response() {
+        let response = FirehoseResponse {
+            records: vec![FirehoseResponseRecord {
+                record_id: "1".into(),
+                result: FirehoseResult::ProcessingFailed,
+                data: "SGVsbG8=".into(),
+            }],
+        };
+
+        assert_eq!(
+            r#"{"records":[{"recordId":"1","result":"ProcessingFailed","data":"SGVsbG8="}]}"#,
+            serde_json::to_string(&response).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transformed_data() {
+        let data = LogData::decode(&firehose_record("1", "DATA_MESSAGE").data)
+            .unwrap()
+            .with_events(vec![
+                LogEntry {
+                    id: "1".into(),
+                    timestamp: 0,
+                    message: "hello".into(),
+                },
+                LogEntry {
+                    id: "2".into(),
+                    timestamp: 1,
+                    message: "START RequestId".into(),
+                },
+            ]);
+        let report = |sent_ids: &[&str]| InvocationReport {
+            destinations: vec![DestinationReport {
+                delivery: DeliveryReport {
+                    sent_ids: sent_ids.iter().map(|id| id.to_string()).collect(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }],
+            retried: false,
+        };
+
+        // only the delivered events are kept
+        let encoded = transformed_data(&data, &report(&["1"])).unwrap();
+        assert_eq!(
+            "{\"id\":\"1\",\"timestamp\":0,\"message\":\"hello\"}\n",
+            String::from_utf8(base64::decode(encoded).unwrap()).unwrap()
+        );
+
+        // records without delivered events are dropped
+        assert_eq!(None, transformed_data(&data, &report(&[])));
+    }
+
+    #[tokio::test]
+    async fn test_handle_firehose_events() -> Result<(), RuntimeError> {
+        // GIVEN a store without functions and clients that fail if they are used
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let event = FirehoseEvent {
+            records: vec![
+                firehose_record("1", "CONTROL_MESSAGE"),
+                firehose_record("2", "DATA_MESSAGE"),
+                FirehoseEventRecord {
+                    record_id: "3".into(),
+                    data: "invalid".into(),
+                },
+            ],
+            ..Default::default()
+        };
+
+        // WHEN transforming the records
+        let response = handle_firehose_events(
+            &sts_client,
+            &CredentialCache::new(),
+            &MemoryStore::default(),
+            &Config::default(),
+            &StreamCache::new(),
+            LambdaEvent::new(event.clone(), Context::default()),
+        )
+        .await?;
+
+        // THEN control messages are dropped and the other records fail
+        let results: Vec<(&str, FirehoseResult)> = response
+            .records
+            .iter()
+            .map(|record| (record.record_id.as_str(), record.result))
+            .collect();
+        assert_eq!(
+            vec![
+                ("1", FirehoseResult::Dropped),
+                ("2", FirehoseResult::ProcessingFailed),
+                ("3", FirehoseResult::ProcessingFailed),
+            ],
+            results
+        );
+        assert_eq!(event.records[1].data, response.records[1].data);
+
+        Ok(())
+    }
+}
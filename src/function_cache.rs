@@ -0,0 +1,200 @@
+use crate::{error::RuntimeError, function_info::FunctionInfo, ttl_map::TtlMap};
+use std::time::{Duration, Instant};
+
+/// Time that functions stay in the cache when the environment doesn't set one
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// Time that missing and invalid functions stay in the cache
+/// when the environment doesn't set one
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(60);
+
+/// Number of functions in the cache when the environment doesn't set one
+pub const DEFAULT_MAX_SIZE: usize = 1000;
+
+/// Result of a function lookup, as it's kept in the cache
+#[derive(Clone, Debug, PartialEq)]
+enum CachedLookup {
+    Found(Box<FunctionInfo>),
+    MissingFunction(String),
+    MissingField(String),
+    InvalidField(String),
+    InvalidTemplate(String),
+}
+
+impl CachedLookup {
+    /// Entry for the result of a lookup, or None if the lookup may succeed
+    /// when it's done again, like after a DynamoDB throttling error
+    fn new(res: &Result<FunctionInfo, RuntimeError>) -> Option<CachedLookup> {
+        match res {
+            Ok(info) => Some(CachedLookup::Found(Box::new(info.clone()))),
+            Err(RuntimeError::MissingFunction(id)) => {
+                Some(CachedLookup::MissingFunction(id.clone()))
+            }
+            Err(RuntimeError::MissingField(f)) => Some(CachedLookup::MissingField(f.clone())),
+            Err(RuntimeError::InvalidField(f)) => Some(CachedLookup::InvalidField(f.clone())),
+            Err(RuntimeError::InvalidTemplate(t)) => Some(CachedLookup::InvalidTemplate(t.clone())),
+            Err(_) => None,
+        }
+    }
+
+    fn into_result(self) -> Result<FunctionInfo, RuntimeError> {
+        match self {
+            CachedLookup::Found(info) => Ok(*info),
+            CachedLookup::MissingFunction(id) => Err(RuntimeError::MissingFunction(id)),
+            CachedLookup::MissingField(f) => Err(RuntimeError::MissingField(f)),
+            CachedLookup::InvalidField(f) => Err(RuntimeError::InvalidField(f)),
+            CachedLookup::InvalidTemplate(t) => Err(RuntimeError::InvalidTemplate(t)),
+        }
+    }
+}
+
+/// `FunctionInfoCache` keeps the function information in memory
+/// to avoid DynamoDB requests on warm invocations.
+///
+/// Functions that don't exist, or whose items are invalid, are also cached
+/// with a different ttl, so their events don't hit DynamoDB on every invocation
+/// until the item is created or fixed.
+#[derive(Debug)]
+pub struct FunctionInfoCache {
+    ttl: Duration,
+    negative_ttl: Duration,
+    entries: TtlMap<String, CachedLookup>,
+}
+
+impl Default for FunctionInfoCache {
+    fn default() -> Self {
+        FunctionInfoCache::new(DEFAULT_TTL, DEFAULT_NEGATIVE_TTL, DEFAULT_MAX_SIZE)
+    }
+}
+
+impl FunctionInfoCache {
+    /// Create a new cache that keeps up to `max_size` functions
+    pub fn new(ttl: Duration, negative_ttl: Duration, max_size: usize) -> FunctionInfoCache {
+        FunctionInfoCache {
+            ttl,
+            negative_ttl,
+            entries: TtlMap::new(max_size),
+        }
+    }
+
+    /// Get the result of a function lookup from the cache.
+    /// It returns None if the function is not in the cache, and the error
+    /// of the lookup if the function is known to be missing or invalid.
+    pub fn get(&self, id: &str) -> Option<Result<FunctionInfo, RuntimeError>> {
+        self.get_at(id, Instant::now())
+    }
+
+    fn get_at(&self, id: &str, now: Instant) -> Option<Result<FunctionInfo, RuntimeError>> {
+        self.entries
+            .get(&id.to_owned(), now)
+            .map(CachedLookup::into_result)
+    }
+
+    /// Store the result of a function lookup.
+    /// Errors that may not happen again, like DynamoDB errors, are not stored.
+    pub fn insert(&self, id: &str, res: &Result<FunctionInfo, RuntimeError>) {
+        self.insert_at(id, res, Instant::now())
+    }
+
+    fn insert_at(&self, id: &str, res: &Result<FunctionInfo, RuntimeError>, now: Instant) {
+        let entry = match CachedLookup::new(res) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let ttl = match entry {
+            CachedLookup::Found(_) => self.ttl,
+            _ => self.negative_ttl,
+        };
+        self.entries.insert(id.to_owned(), entry, ttl, now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn found(id: &str) -> Result<FunctionInfo, RuntimeError> {
+        Ok(FunctionInfo {
+            id: id.into(),
+            ..Default::default()
+        })
+    }
+
+    fn found_id(res: Option<Result<FunctionInfo, RuntimeError>>) -> Option<String> {
+        res.and_then(|res| res.ok()).map(|info| info.id)
+    }
+
+    #[test]
+    fn test_cache_expiration() {
+        let cache = FunctionInfoCache::new(Duration::from_secs(10), Duration::from_secs(5), 10);
+        let now = Instant::now();
+
+        cache.insert_at("1", &found("1"), now);
+        cache.insert_at("2", &Err(RuntimeError::MissingFunction("2".into())), now);
+
+        assert_eq!(Some("1".into()), found_id(cache.get_at("1", now)));
+        assert!(matches!(
+            cache.get_at("2", now),
+            Some(Err(RuntimeError::MissingFunction(_)))
+        ));
+        assert!(cache.get_at("3", now).is_none());
+
+        let later = now + Duration::from_secs(6);
+        assert_eq!(Some("1".into()), found_id(cache.get_at("1", later)));
+        assert!(cache.get_at("2", later).is_none());
+
+        let much_later = now + Duration::from_secs(11);
+        assert!(cache.get_at("1", much_later).is_none());
+    }
+
+    #[test]
+    fn test_cache_invalid_functions() {
+        let cache = FunctionInfoCache::new(Duration::from_secs(10), Duration::from_secs(5), 10);
+        let now = Instant::now();
+
+        cache.insert_at("1", &Err(RuntimeError::MissingField("name".into())), now);
+        cache.insert_at("2", &Err(RuntimeError::InvalidField("tags".into())), now);
+        cache.insert_at("3", &Err(RuntimeError::InvalidTemplate("{x}".into())), now);
+        cache.insert_at("4", &Err(RuntimeError::MissingCredentials), now);
+
+        assert!(matches!(
+            cache.get_at("1", now),
+            Some(Err(RuntimeError::MissingField(f))) if f == "name"
+        ));
+        assert!(matches!(
+            cache.get_at("2", now),
+            Some(Err(RuntimeError::InvalidField(f))) if f == "tags"
+        ));
+        assert!(matches!(
+            cache.get_at("3", now),
+            Some(Err(RuntimeError::InvalidTemplate(_)))
+        ));
+        // errors that may not happen again are not cached
+        assert!(cache.get_at("4", now).is_none());
+
+        // invalid functions expire like missing functions
+        assert!(cache.get_at("1", now + Duration::from_secs(6)).is_none());
+    }
+
+    #[test]
+    fn test_cache_max_size() {
+        let cache = FunctionInfoCache::new(Duration::from_secs(10), Duration::from_secs(5), 2);
+        let now = Instant::now();
+
+        cache.insert_at("1", &found("1"), now);
+        cache.insert_at("2", &found("2"), now + Duration::from_secs(1));
+        cache.insert_at("3", &found("3"), now + Duration::from_secs(2));
+
+        assert!(cache.get_at("1", now).is_none());
+        assert!(cache.get_at("2", now).is_some());
+        assert!(cache.get_at("3", now).is_some());
+    }
+
+    #[test]
+    fn test_disabled_cache() {
+        let cache = FunctionInfoCache::new(Duration::from_secs(10), Duration::from_secs(5), 0);
+
+        cache.insert("1", &found("1"));
+        assert!(cache.get("1").is_none());
+    }
+}
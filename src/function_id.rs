@@ -0,0 +1,174 @@
+use crate::error::RuntimeError;
+use regex::Regex;
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+/// `FunctionIdResolver` extracts the id of a function from
+/// the name of the log group where its events were published
+pub trait FunctionIdResolver: Debug + Send + Sync {
+    /// Find the function id for a log group, if the log group belongs to a function
+    fn resolve(&self, log_group: &str) -> Option<String>;
+}
+
+/// Use the last segment of the log group name as the function id,
+/// like `function` in `/aws/lambda/function`.
+///
+/// Trailing slashes are ignored, and the region prefix that Lambda@Edge
+/// adds to its log groups, like `us-east-1.` in `/aws/lambda/us-east-1.function`,
+/// is removed.
+#[derive(Clone, Debug, Default)]
+pub struct LastSegmentResolver;
+
+impl FunctionIdResolver for LastSegmentResolver {
+    fn resolve(&self, log_group: &str) -> Option<String> {
+        let segment = log_group.trim_end_matches('/').rsplit('/').next()?;
+
+        let id = match segment.split_once('.') {
+            Some((region, id)) if is_region(region) => id,
+            _ => segment,
+        };
+
+        Some(id.to_owned()).filter(|id| !id.is_empty())
+    }
+}
+
+/// Check if a string looks like an AWS region, like `us-east-1`
+fn is_region(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('-').collect();
+    parts.len() >= 3
+        && parts[..parts.len() - 1]
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_lowercase()))
+        && parts[parts.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Extract the function id with a regular expression.
+/// The id is the capture group named `id`, or the first capture group
+/// if the expression doesn't have named groups.
+#[derive(Clone, Debug)]
+pub struct RegexResolver {
+    regex: Regex,
+}
+
+impl RegexResolver {
+    /// Create a resolver from a regular expression
+    pub fn new(pattern: &str) -> Result<RegexResolver, RuntimeError> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| RuntimeError::InvalidConfig(format!("invalid function id regex: {e}")))?;
+        if regex.captures_len() < 2 {
+            return Err(RuntimeError::InvalidConfig(format!(
+                "function id regex {pattern} doesn't have capture groups"
+            )));
+        }
+        Ok(RegexResolver { regex })
+    }
+}
+
+impl FunctionIdResolver for RegexResolver {
+    fn resolve(&self, log_group: &str) -> Option<String> {
+        let captures = self.regex.captures(log_group)?;
+        let id = captures.name("id").or_else(|| captures.get(1))?;
+        Some(id.as_str().to_owned()).filter(|id| !id.is_empty())
+    }
+}
+
+/// Find the function id in a table of log group names
+#[derive(Clone, Debug, Default)]
+pub struct LookupTableResolver {
+    table: HashMap<String, String>,
+}
+
+impl LookupTableResolver {
+    /// Create a resolver from a map of log group names to function ids
+    pub fn new(table: HashMap<String, String>) -> LookupTableResolver {
+        LookupTableResolver { table }
+    }
+}
+
+impl FunctionIdResolver for LookupTableResolver {
+    fn resolve(&self, log_group: &str) -> Option<String> {
+        self.table.get(log_group).cloned()
+    }
+}
+
+/// Create a resolver from its description.
+///
+/// Resolvers are written as `last_segment`, `regex:<pattern>`,
+/// or `lookup:<JSON object of log group names to function ids>`.
+pub fn resolver_from_str(s: &str) -> Result<Arc<dyn FunctionIdResolver>, RuntimeError> {
+    match s.split_once(':') {
+        None if s == "last_segment" => Ok(Arc::new(LastSegmentResolver)),
+        Some(("regex", pattern)) => Ok(Arc::new(RegexResolver::new(pattern)?)),
+        Some(("lookup", json)) => {
+            let table = serde_json::from_str(json).map_err(|e| {
+                RuntimeError::InvalidConfig(format!("invalid function id lookup table: {e}"))
+            })?;
+            Ok(Arc::new(LookupTableResolver::new(table)))
+        }
+        _ => Err(RuntimeError::InvalidConfig(format!(
+            "unknown function id resolver {s}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_last_segment_resolver() {
+        let resolver = LastSegmentResolver;
+        assert_eq!(
+            Some("function".into()),
+            resolver.resolve("/aws/lambda/function")
+        );
+        assert_eq!(
+            Some("function".into()),
+            resolver.resolve("/aws/lambda/function/")
+        );
+        assert_eq!(
+            Some("function".into()),
+            resolver.resolve("/aws/lambda/us-east-1.function")
+        );
+        assert_eq!(
+            Some("my.function".into()),
+            resolver.resolve("/aws/lambda/my.function")
+        );
+        assert_eq!(Some("function".into()), resolver.resolve("function"));
+        assert_eq!(None, resolver.resolve("/"));
+        assert_eq!(None, resolver.resolve(""));
+    }
+
+    #[test]
+    fn test_regex_resolver() {
+        let resolver = RegexResolver::new(r"^/aws/lambda/(?P<id>[^/]+)$").unwrap();
+        assert_eq!(
+            Some("function".into()),
+            resolver.resolve("/aws/lambda/function")
+        );
+        assert_eq!(None, resolver.resolve("/aws/ecs/function"));
+
+        let resolver = RegexResolver::new(r"^/custom/([^/]+)/logs$").unwrap();
+        assert_eq!(
+            Some("function".into()),
+            resolver.resolve("/custom/function/logs")
+        );
+
+        assert!(RegexResolver::new(r"^/aws/lambda/.+$").is_err());
+        assert!(RegexResolver::new(r"(").is_err());
+    }
+
+    #[test]
+    fn test_lookup_table_resolver() {
+        let resolver = resolver_from_str(r#"lookup:{"/ecs/service": "function"}"#).unwrap();
+        assert_eq!(Some("function".into()), resolver.resolve("/ecs/service"));
+        assert_eq!(None, resolver.resolve("/ecs/other"));
+    }
+
+    #[test]
+    fn test_resolver_from_str() {
+        assert!(resolver_from_str("last_segment").is_ok());
+        assert!(resolver_from_str("regex:/aws/lambda/(.+)").is_ok());
+        assert!(resolver_from_str("lookup:[]").is_err());
+        assert!(resolver_from_str("unknown").is_err());
+    }
+}
@@ -1,7 +1,142 @@
+use crate::{
+    cloudwatch_logs::is_valid_retention, error::RuntimeError, filter::LogFilter,
+    naming::LogStreamStrategy, sts::AssumeRoleOptions, sts::SESSION_DURATION_SECONDS,
+    template::Template,
+};
+use std::collections::{BTreeMap, HashMap};
+
 /// `FunctionInfo` stores information about the function invoked
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct FunctionInfo {
     pub id: String,
     pub name: String,
     pub cloudwatch_logs_assume_role_arn: String,
+    pub app_id: Option<String>,
+    pub branch: Option<String>,
+    pub log_group_template: Option<Template>,
+    pub log_stream_strategy: Option<LogStreamStrategy>,
+    pub log_filter: Option<LogFilter>,
+    pub retention_in_days: Option<i32>,
+    pub kms_key_id: Option<String>,
+    pub tags: HashMap<String, String>,
+    pub external_id: Option<String>,
+    pub session_duration_seconds: Option<i32>,
+    pub source_identity: Option<String>,
+    pub tag_session: bool,
+    pub archive_bucket: Option<String>,
+}
+
+impl FunctionInfo {
+    /// Options to assume the CloudWatch Logs role of the function.
+    /// Sessions are tagged with the tenant and function ids when `tag_session` is set,
+    /// which requires the `sts:TagSession` permission in the trust policy of the role.
+    pub fn assume_role_options(&self) -> AssumeRoleOptions {
+        let mut session_tags = BTreeMap::new();
+        if self.tag_session {
+            session_tags.insert("FunctionId".to_owned(), self.id.clone());
+            if let Some(app_id) = &self.app_id {
+                session_tags.insert("TenantId".to_owned(), app_id.clone());
+            }
+        }
+
+        AssumeRoleOptions {
+            external_id: self.external_id.clone(),
+            duration_seconds: self.session_duration_seconds,
+            session_tags,
+            source_identity: self.source_identity.clone(),
+        }
+    }
+}
+
+// Validation of the function fields, shared by the function stores.
+// Invalid values are reported as `RuntimeError::InvalidField`.
+
+/// Parse the log stream strategy of a function
+pub fn parse_log_stream_strategy(strategy: &str) -> Result<LogStreamStrategy, RuntimeError> {
+    strategy
+        .parse()
+        .map_err(|_| RuntimeError::InvalidField("log_stream_strategy".into()))
+}
+
+/// Check that CloudWatch accepts the retention of a function
+pub fn validate_retention_in_days(days: i32) -> Result<i32, RuntimeError> {
+    match days {
+        days if is_valid_retention(days) => Ok(days),
+        _ => Err(RuntimeError::InvalidField("retention_in_days".into())),
+    }
+}
+
+/// Check that STS accepts the session duration of a function
+pub fn validate_session_duration(seconds: i32) -> Result<i32, RuntimeError> {
+    match seconds {
+        seconds if SESSION_DURATION_SECONDS.contains(&seconds) => Ok(seconds),
+        _ => Err(RuntimeError::InvalidField(
+            "session_duration_seconds".into(),
+        )),
+    }
+}
+
+/// Check that the archive bucket of a function is a valid S3 bucket name:
+/// 3 to 63 lowercase letters, numbers, dots and hyphens,
+/// starting and ending with a letter or number
+pub fn validate_archive_bucket(bucket: String) -> Result<String, RuntimeError> {
+    let valid_chars = bucket
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-');
+    let valid_ends = bucket.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && bucket.ends_with(|c: char| c.is_ascii_alphanumeric());
+
+    if (3..=63).contains(&bucket.len()) && valid_chars && valid_ends {
+        Ok(bucket)
+    } else {
+        Err(RuntimeError::InvalidField("archive_bucket".into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_assume_role_options() {
+        let mut info = FunctionInfo {
+            id: "1".into(),
+            app_id: Some("app".into()),
+            external_id: Some("external".into()),
+            ..Default::default()
+        };
+
+        let options = info.assume_role_options();
+        assert_eq!(Some("external".into()), options.external_id);
+        assert!(options.session_tags.is_empty());
+
+        info.tag_session = true;
+        let options = info.assume_role_options();
+        assert_eq!(
+            Some(&"1".to_string()),
+            options.session_tags.get("FunctionId")
+        );
+        assert_eq!(
+            Some(&"app".to_string()),
+            options.session_tags.get("TenantId")
+        );
+    }
+
+    #[test]
+    fn test_validate_fields() {
+        assert!(parse_log_stream_strategy("date").is_ok());
+        assert!(parse_log_stream_strategy("other").is_err());
+        assert!(validate_retention_in_days(30).is_ok());
+        assert!(validate_retention_in_days(31).is_err());
+        assert!(validate_session_duration(3600).is_ok());
+        assert!(validate_session_duration(60).is_err());
+
+        assert!(validate_archive_bucket("my-logs.archive".into()).is_ok());
+        for bucket in ["ab", "My-Logs", "logs-", "logs_archive", &"a".repeat(64)] {
+            assert!(matches!(
+                validate_archive_bucket(bucket.into()),
+                Err(RuntimeError::InvalidField(_))
+            ));
+        }
+    }
 }
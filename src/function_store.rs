@@ -0,0 +1,250 @@
+use crate::{
+    error::RuntimeError,
+    filter::LogFilter,
+    function_info::{
+        parse_log_stream_strategy, validate_archive_bucket, validate_retention_in_days,
+        validate_session_duration, FunctionInfo,
+    },
+    naming::log_group_template,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+/// `FunctionInfoStore` is the registry that maps function ids
+/// to the settings used to deliver their logs
+#[async_trait]
+pub trait FunctionInfoStore: Send + Sync {
+    /// Fetch the function information to locate the assume role arn.
+    /// It returns `RuntimeError::MissingFunction` if the function is not registered.
+    async fn get_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError>;
+}
+
+/// Store that keeps the functions in memory
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    functions: HashMap<String, FunctionInfo>,
+}
+
+impl MemoryStore {
+    /// Create a store with a list of functions, indexed by their id
+    pub fn new(functions: impl IntoIterator<Item = FunctionInfo>) -> MemoryStore {
+        MemoryStore {
+            functions: functions
+                .into_iter()
+                .map(|info| (info.id.clone(), info))
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl FunctionInfoStore for MemoryStore {
+    async fn get_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError> {
+        self.functions
+            .get(id)
+            .cloned()
+            .ok_or_else(|| RuntimeError::MissingFunction(id.into()))
+    }
+}
+
+/// Store that loads the functions from a JSON or YAML file.
+///
+/// The file has a list of functions with the same fields as the DynamoDB items.
+/// Files with the `.yaml` or `.yml` extensions are parsed as YAML,
+/// anything else is parsed as JSON.
+#[derive(Clone, Debug, Default)]
+pub struct FileStore {
+    inner: MemoryStore,
+}
+
+impl FileStore {
+    /// Load the functions from a file
+    #[tracing::instrument]
+    pub fn load(path: &Path) -> Result<FileStore, RuntimeError> {
+        tracing::info!("Loading functions from file");
+        let invalid =
+            |reason: String| RuntimeError::InvalidConfig(format!("{}: {reason}", path.display()));
+
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let is_yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml" | "yml")
+        );
+
+        let records: Vec<FunctionRecord> = if is_yaml {
+            serde_yaml::from_str(&contents).map_err(|e| invalid(e.to_string()))?
+        } else {
+            serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?
+        };
+
+        let functions = records
+            .into_iter()
+            .map(FunctionInfo::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(FileStore {
+            inner: MemoryStore::new(functions),
+        })
+    }
+}
+
+#[async_trait]
+impl FunctionInfoStore for FileStore {
+    async fn get_function_info(&self, id: &str) -> Result<FunctionInfo, RuntimeError> {
+        self.inner.get_function_info(id).await
+    }
+}
+
+/// Function as written in a file, with the same fields as the DynamoDB items
+#[derive(Debug, Deserialize)]
+struct FunctionRecord {
+    id: String,
+    name: String,
+    cloudwatch_logs_assume_role_arn: String,
+    app_id: Option<String>,
+    branch: Option<String>,
+    log_group_template: Option<String>,
+    log_stream_strategy: Option<String>,
+    log_filters: Option<LogFilter>,
+    retention_in_days: Option<i32>,
+    kms_key_id: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    external_id: Option<String>,
+    session_duration_seconds: Option<i32>,
+    source_identity: Option<String>,
+    #[serde(default)]
+    tag_session: bool,
+    archive_bucket: Option<String>,
+}
+
+impl TryFrom<FunctionRecord> for FunctionInfo {
+    type Error = RuntimeError;
+
+    fn try_from(record: FunctionRecord) -> Result<Self, Self::Error> {
+        Ok(FunctionInfo {
+            id: record.id,
+            name: record.name,
+            cloudwatch_logs_assume_role_arn: record.cloudwatch_logs_assume_role_arn,
+            app_id: record.app_id,
+            branch: record.branch,
+            log_group_template: record
+                .log_group_template
+                .map(|template| log_group_template(&template))
+                .transpose()?,
+            log_stream_strategy: record
+                .log_stream_strategy
+                .map(|strategy| parse_log_stream_strategy(&strategy))
+                .transpose()?,
+            log_filter: record.log_filters,
+            retention_in_days: record
+                .retention_in_days
+                .map(validate_retention_in_days)
+                .transpose()?,
+            kms_key_id: record.kms_key_id,
+            tags: record.tags,
+            external_id: record.external_id,
+            session_duration_seconds: record
+                .session_duration_seconds
+                .map(validate_session_duration)
+                .transpose()?,
+            source_identity: record.source_identity,
+            tag_session: record.tag_session,
+            archive_bucket: record
+                .archive_bucket
+                .map(validate_archive_bucket)
+                .transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::naming::LogStreamStrategy;
+    use std::io::Write;
+
+    fn write_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        std::fs::File::create(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::new([FunctionInfo {
+            id: "1".into(),
+            name: "function".into(),
+            ..Default::default()
+        }]);
+
+        assert_eq!("function", store.get_function_info("1").await.unwrap().name);
+        assert!(matches!(
+            store.get_function_info("2").await,
+            Err(RuntimeError::MissingFunction(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_json_file_store() -> Result<(), RuntimeError> {
+        let path = write_file(
+            "functions.json",
+            r#"[{"id": "1", "name": "function", "cloudwatch_logs_assume_role_arn": "arn",
+                "log_stream_strategy": "date", "retention_in_days": 30, "archive_bucket": "logs",
+                "log_filters": [{"action": "exclude", "prefix": "START"}]}]"#,
+        );
+        let store = FileStore::load(&path)?;
+        std::fs::remove_file(&path).unwrap();
+
+        let function = store.get_function_info("1").await?;
+        assert_eq!("arn", function.cloudwatch_logs_assume_role_arn);
+        assert_eq!(
+            Some(LogStreamStrategy::DateBucketed),
+            function.log_stream_strategy
+        );
+        assert_eq!(Some(30), function.retention_in_days);
+        assert!(function.log_filter.is_some());
+        assert_eq!(Some("logs".into()), function.archive_bucket);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_yaml_file_store() -> Result<(), RuntimeError> {
+        let path = write_file(
+            "functions.yaml",
+            "- id: \"1\"\n  name: function\n  cloudwatch_logs_assume_role_arn: arn\n  tags:\n    team: web\n",
+        );
+        let store = FileStore::load(&path)?;
+        std::fs::remove_file(&path).unwrap();
+
+        let function = store.get_function_info("1").await?;
+        assert_eq!("function", function.name);
+        assert_eq!(Some(&"web".to_string()), function.tags.get("team"));
+        assert!(matches!(
+            store.get_function_info("2").await,
+            Err(RuntimeError::MissingFunction(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_file_store() {
+        let path = write_file(
+            "invalid.json",
+            r#"[{"id": "1", "name": "function", "cloudwatch_logs_assume_role_arn": "arn", "retention_in_days": 31}]"#,
+        );
+        let res = FileStore::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(RuntimeError::InvalidField(_))));
+
+        assert!(matches!(
+            FileStore::load(Path::new("/missing/functions.json")),
+            Err(RuntimeError::InvalidConfig(_))
+        ));
+    }
+}
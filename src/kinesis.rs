@@ -0,0 +1,290 @@
+use crate::{
+    deliver_log_data,
+    error::RuntimeError,
+    event::LogData,
+    function_store::FunctionInfoStore,
+    metrics,
+    report::{BatchItemFailure, BatchResponse},
+    retry_queue::{settle_delivery, Settlement},
+    Config, CredentialCache, RetryQueue, StreamCache,
+};
+use aws_sdk_sts::Client as StsClient;
+use lambda_runtime::LambdaEvent;
+use serde::Deserialize;
+
+/// `KinesisEvent` is the batch of records that Lambda reads from a Kinesis stream
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct KinesisEvent {
+    /// Records in the batch
+    #[serde(rename = "Records")]
+    pub records: Vec<KinesisEventRecord>,
+}
+
+/// `KinesisEventRecord` is a record in a Kinesis batch
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct KinesisEventRecord {
+    /// Kinesis record data
+    pub kinesis: KinesisRecord,
+}
+
+/// `KinesisRecord` has the data published in the stream
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KinesisRecord {
+    /// Sequence number of the record in the shard
+    pub sequence_number: String,
+    /// Partition key of the record
+    pub partition_key: String,
+    /// Base64 gzipped CloudWatch Logs payload
+    pub data: String,
+}
+
+/// Consecutive records from the same log stream, delivered together
+#[derive(Debug)]
+struct RecordGroup {
+    data: LogData,
+    sequence_numbers: Vec<String>,
+}
+
+/// Decode the records and merge the consecutive ones that come from the same log stream.
+/// Only consecutive records are merged, so the groups keep the order of the shard.
+/// Records that can't be decoded are dropped, as retrying them would block the shard.
+fn group_records(records: &[KinesisEventRecord]) -> Vec<RecordGroup> {
+    let mut groups: Vec<RecordGroup> = Vec::new();
+
+    for record in records {
+        let data = match LogData::decode(&record.kinesis.data) {
+            Ok(data) => data,
+            Err(err) => {
+                tracing::error!(
+                    sequence_number = %record.kinesis.sequence_number,
+                    "dropping invalid record: {err}"
+                );
+                metrics::count("InvalidRecords", 1);
+                continue;
+            }
+        };
+
+        let group = groups.last_mut().filter(|group| {
+            group.data.log_group == data.log_group
+                && group.data.log_stream == data.log_stream
+                && group.data.message_type == data.message_type
+        });
+
+        let sequence_number = record.kinesis.sequence_number.clone();
+        match group {
+            Some(group) => {
+                group.data.log_events.extend(data.log_events);
+                group.sequence_numbers.push(sequence_number);
+            }
+            None => groups.push(RecordGroup {
+                data,
+                sequence_numbers: vec![sequence_number],
+            }),
+        }
+    }
+
+    groups
+}
+
+/// `handle_kinesis_events` is the Lambda function entry point
+/// that receives CloudWatch Logs subscriptions through a Kinesis stream.
+///
+/// Failed deliveries follow the policy of `settle_delivery`.
+/// Lambda retries the shard from the lowest sequence number in the batch item failures,
+/// so the groups are delivered in order, and the first group where nothing was delivered
+/// stops the invocation: its first sequence number is the only failure reported,
+/// and the groups after it are left to the retry.
+#[tracing::instrument(skip(
+    sts_client,
+    credentials,
+    function_store,
+    config,
+    cache,
+    retry_queue,
+    event
+))]
+pub async fn handle_kinesis_events<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    retry_queue: Option<&RetryQueue>,
+    event: LambdaEvent<KinesisEvent>,
+) -> Result<BatchResponse, RuntimeError> {
+    let request_id = event.context.request_id;
+    let mut response = BatchResponse::default();
+
+    for group in group_records(&event.payload.records) {
+        let report = deliver_log_data(
+            sts_client,
+            credentials,
+            function_store,
+            config,
+            cache,
+            &request_id,
+            &group.data,
+            &[],
+        )
+        .await;
+
+        match settle_delivery(&group.data, &report, retry_queue, 1).await {
+            Settlement::Failed(_) => {
+                response.batch_item_failures.push(BatchItemFailure {
+                    item_identifier: group.sequence_numbers[0].clone(),
+                });
+                break;
+            }
+            Settlement::Dropped => {
+                metrics::count("DroppedRecords", group.sequence_numbers.len());
+            }
+            Settlement::Delivered | Settlement::Retried => {}
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{function_store::MemoryStore, test_util::*};
+    use lambda_runtime::Context;
+
+    #[test]
+    fn test_deserialize_kinesis_event() {
+        let json = r#"{"Records": [{"kinesis": {"kinesisSchemaVersion": "1.0",
+            "partitionKey": "key", "sequenceNumber": "1", "data": "SGVsbG8=",
+            "approximateArrivalTimestamp": 1545084650.987}, "eventSource": "aws:kinesis",
+            "eventID": "shardId-000000000006:1"}]}"#;
+
+        let event: KinesisEvent = serde_json::from_str(json).expect("failed to deserialize");
+        assert_eq!(1, event.records.len());
+        assert_eq!("1", event.records[0].kinesis.sequence_number);
+        assert_eq!("SGVsbG8=", event.records[0].kinesis.data);
+    }
+
+    #[test]
+    fn test_group_records() {
+        let records = [
+            kinesis_record("1", encoded_log_data("DATA_MESSAGE", "a", "1")),
+            kinesis_record("2", encoded_log_data("DATA_MESSAGE", "b", "2")),
+            kinesis_record("3", "invalid".into()),
+            kinesis_record("4", encoded_log_data("DATA_MESSAGE", "a", "3")),
+            kinesis_record("5", encoded_log_data("DATA_MESSAGE", "a", "4")),
+        ];
+
+        let groups = group_records(&records);
+        assert_eq!(3, groups.len());
+        assert_eq!(vec!["1"], groups[0].sequence_numbers);
+        assert_eq!(vec!["2"], groups[1].sequence_numbers);
+        assert_eq!(vec!["4", "5"], groups[2].sequence_numbers);
+        assert_eq!(2, groups[2].data.log_events.len());
+    }
+
+    #[tokio::test]
+    async fn test_handle_kinesis_events() -> Result<(), RuntimeError> {
+        // GIVEN a store without functions and clients that fail if they are used
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let event = KinesisEvent {
+            records: vec![
+                kinesis_record("1", encoded_log_data("CONTROL_MESSAGE", "a", "1")),
+                kinesis_record("2", encoded_log_data("DATA_MESSAGE", "a", "2")),
+                kinesis_record("3", "invalid".into()),
+                kinesis_record("4", encoded_log_data("DATA_MESSAGE", "a", "3")),
+            ],
+        };
+
+        // WHEN handling the records
+        let response = handle_kinesis_events(
+            &sts_client,
+            &CredentialCache::new(),
+            &MemoryStore::default(),
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            LambdaEvent::new(event, Context::default()),
+        )
+        .await?;
+
+        // THEN the records of the missing function are dropped instead of retried
+        assert!(response.batch_item_failures.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_kinesis_events_with_transient_error() -> Result<(), RuntimeError> {
+        // GIVEN a function whose role can't be assumed
+        let sts_client = get_denied_sts_client(&get_mock_config().await, 1);
+        let function_store = MemoryStore::new([function_info()]);
+        let event = KinesisEvent {
+            records: vec![
+                kinesis_record("1", encoded_log_data("DATA_MESSAGE", "a", "1")),
+                kinesis_record("2", encoded_log_data("DATA_MESSAGE", "a", "2")),
+            ],
+        };
+
+        // WHEN handling the records
+        let response = handle_kinesis_events(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            LambdaEvent::new(event, Context::default()),
+        )
+        .await?;
+
+        // THEN the shard is retried from the first record
+        let failures: Vec<&str> = response
+            .batch_item_failures
+            .iter()
+            .map(|failure| failure.item_identifier.as_str())
+            .collect();
+        assert_eq!(vec!["1"], failures);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_kinesis_events_stops_at_first_failure() -> Result<(), RuntimeError> {
+        // GIVEN a function whose role can't be assumed once,
+        // and a failing group before another log stream and more records of the first one
+        let sts_client = get_denied_sts_client(&get_mock_config().await, 1);
+        let function_store = MemoryStore::new([function_info()]);
+        let event = KinesisEvent {
+            records: vec![
+                kinesis_record("1", encoded_log_data("DATA_MESSAGE", "a", "1")),
+                kinesis_record("2", encoded_log_data("DATA_MESSAGE", "b", "2")),
+                kinesis_record("3", encoded_log_data("DATA_MESSAGE", "a", "3")),
+            ],
+        };
+
+        // WHEN handling the records
+        let response = handle_kinesis_events(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            LambdaEvent::new(event, Context::default()),
+        )
+        .await?;
+
+        // THEN only the lowest failed sequence number is reported, and the records after it
+        // are not delivered, so the retry from that record doesn't send anything twice
+        let failures: Vec<&str> = response
+            .batch_item_failures
+            .iter()
+            .map(|failure| failure.item_identifier.as_str())
+            .collect();
+        assert_eq!(vec!["1"], failures);
+
+        Ok(())
+    }
+}
@@ -4,12 +4,20 @@
 //! Lambda function that receives log events
 //! from CloudWatch Logs. It tries to find who the invocation
 //! belongs to, and sends the event to the owner's account.
-use aws_sdk_cloudwatchlogs::Client as CwClient;
 use aws_sdk_sts::Client as StsClient;
 use lambda_runtime::LambdaEvent;
 
+mod batch;
+
 mod cloudwatch_logs;
 use cloudwatch_logs::*;
+pub use cloudwatch_logs::{CloudWatchLogsSink, Destination};
+
+mod config;
+pub use config::{Config, EventSource};
+
+mod credential_cache;
+pub use credential_cache::{CredentialCache, RoleClients};
 
 mod dynamodb_ext;
 
@@ -18,8 +26,65 @@ pub use error::RuntimeError;
 
 mod event;
 pub use event::LogsEvent;
+use event::{LogData, MessageType};
+
+mod filter;
+pub use filter::{FilterAction, FilterRule, Level, LogFilter, Matcher};
+
+mod firehose;
+pub use firehose::{
+    handle_firehose_events, FirehoseEvent, FirehoseEventRecord, FirehoseResponse,
+    FirehoseResponseRecord, FirehoseResult,
+};
+
+mod function_cache;
+pub use function_cache::FunctionInfoCache;
+
+mod function_id;
+pub use function_id::{
+    FunctionIdResolver, LastSegmentResolver, LookupTableResolver, RegexResolver,
+};
 
 mod function_info;
+use function_info::FunctionInfo;
+
+mod function_store;
+pub use function_store::{FileStore, FunctionInfoStore, MemoryStore};
+
+mod kinesis;
+pub use kinesis::{handle_kinesis_events, KinesisEvent, KinesisEventRecord, KinesisRecord};
+
+mod metrics;
+
+mod naming;
+pub use naming::LogStreamStrategy;
+
+mod normalize;
+pub use normalize::{NormalizeReport, OutOfRangePolicy};
+
+mod report;
+pub use report::{
+    BatchItemFailure, BatchResponse, DeliveryReport, DestinationReport, InvocationReport,
+    LogGroupDrift, Outcome, RejectedEvent, RejectionReason,
+};
+
+mod retry_queue;
+pub use retry_queue::{handle_retry_messages, RetryMessage, RetryQueue, SqsEvent, SqsMessage};
+use retry_queue::{settle_delivery, Settlement};
+
+mod s3;
+pub use s3::{ArchiveDestination, S3Sink};
+
+mod sink;
+pub use sink::{LogSink, SinkDestination, SinkKind};
+
+mod stream_cache;
+pub use stream_cache::StreamCache;
+
+mod template;
+pub use template::Template;
+
+mod ttl_map;
 
 mod dynamodb;
 pub use dynamodb::DynamoDBClient;
@@ -31,42 +96,439 @@ pub mod sts;
 mod test_util;
 
 /// `handle_logs` is the Lambda function entry point
-/// that receives the events from CloudWatch Logs
-#[tracing::instrument(skip(sts_client, dynamodb_client, event))]
-pub async fn handle_logs(
+/// that receives the events from CloudWatch Logs.
+///
+/// Failed deliveries follow the policy of `settle_delivery`: the events that
+/// a destination didn't receive are sent to the retry queue, and without one,
+/// the invocation fails when nothing was delivered, so Lambda retries it.
+#[tracing::instrument(skip(
+    sts_client,
+    credentials,
+    function_store,
+    config,
+    cache,
+    retry_queue,
+    event
+))]
+pub async fn handle_logs<S: FunctionInfoStore + ?Sized>(
     sts_client: &StsClient,
-    dynamodb_client: &DynamoDBClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    retry_queue: Option<&RetryQueue>,
     event: LambdaEvent<LogsEvent>,
-) -> Result<(), RuntimeError> {
-    let session_id = event.context.request_id;
+) -> Result<InvocationReport, RuntimeError> {
     let data = event.payload.aws_logs.data;
-    let log_group = data.log_group;
 
-    let function_id = match log_group.rsplit('/').next() {
+    // messages that we don't know are not worth retrying
+    data.message_type.parse::<MessageType>()?;
+
+    let mut report = deliver_log_data(
+        sts_client,
+        credentials,
+        function_store,
+        config,
+        cache,
+        &event.context.request_id,
+        &data,
+        &[],
+    )
+    .await;
+
+    match settle_delivery(&data, &report, retry_queue, 1).await {
+        Settlement::Failed(error) => Err(RuntimeError::DeliveryFailed(error)),
+        Settlement::Retried => {
+            report.retried = true;
+            Ok(report)
+        }
+        Settlement::Delivered | Settlement::Dropped => Ok(report),
+    }
+}
+
+/// Deliver a batch of events from a log group to the sinks of the function,
+/// or only to the given kinds of sinks when there are any.
+///
+/// Errors don't stop the invocation, they are recorded in the report
+/// of each destination along with the events delivered before the error.
+/// Control messages don't have a destination, so their report is empty.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(sts_client, credentials, function_store, config, cache, data))]
+pub(crate) async fn deliver_log_data<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    request_id: &str,
+    data: &LogData,
+    kinds: &[SinkKind],
+) -> InvocationReport {
+    let mut report = InvocationReport::default();
+    let source_report = || DestinationReport {
+        source_log_group: data.log_group.clone(),
+        source_log_stream: data.log_stream.clone(),
+        ..Default::default()
+    };
+
+    let res = match data.message_type.parse() {
+        Ok(MessageType::Data) => {
+            function_sinks(
+                sts_client,
+                credentials,
+                function_store,
+                config,
+                cache,
+                request_id,
+                data,
+            )
+            .await
+        }
+        Ok(MessageType::Control) => {
+            tracing::info!("control message received");
+            metrics::count("ControlMessages", 1);
+            return report;
+        }
+        Err(err) => Err(err),
+    };
+
+    let (info, sinks) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            tracing::error!(log_group = %data.log_group, "failed to resolve destinations: {err}");
+            metrics::count("FailedDestinations", 1);
+            report.destinations.push(DestinationReport {
+                error: Some(err.to_string()),
+                retryable: err.is_retryable(),
+                ..source_report()
+            });
+            return report;
+        }
+    };
+
+    let log_filter = info.log_filter.as_ref().unwrap_or(&config.log_filter);
+    let sinks = sinks
+        .into_iter()
+        .filter(|sink| kinds.is_empty() || kinds.contains(&sink.destination().kind()));
+    for sink in sinks {
+        let mut destination = DestinationReport {
+            destination: Some(sink.destination()),
+            ..source_report()
+        };
+
+        let res = sink
+            .send_events(log_filter, &data.log_events, &mut destination.delivery)
+            .await;
+        if let Err(err) = res {
+            tracing::error!(log_group = %data.log_group, "failed to deliver events: {err}");
+            destination.error = Some(err.to_string());
+            destination.retryable = err.is_retryable();
+        }
+
+        let metric = match destination.outcome() {
+            Outcome::Delivered => "DeliveredDestinations",
+            Outcome::Partial => "PartialDestinations",
+            Outcome::Failed => "FailedDestinations",
+        };
+        metrics::count(metric, 1);
+
+        report.destinations.push(destination);
+    }
+
+    report
+}
+
+/// Find the function of the log group, and the sinks where its events are delivered.
+/// Events are always delivered to CloudWatch Logs, and archived in S3
+/// when the function has an archive bucket.
+async fn function_sinks<'a, S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &'a Config,
+    cache: &'a StreamCache,
+    request_id: &str,
+    data: &LogData,
+) -> Result<(FunctionInfo, Vec<Box<dyn LogSink + 'a>>), RuntimeError> {
+    let function_id = match config.function_id_resolver.resolve(&data.log_group) {
         Some(id) => id,
-        _ => return Err(RuntimeError::MissingFunction(log_group)),
+        _ => return Err(RuntimeError::UnresolvedFunctionId(data.log_group.clone())),
     };
 
-    let info = dynamodb_client.get_function_info(function_id).await?;
+    let info = function_store.get_function_info(&function_id).await?;
+
+    // the account owns the destinations, so a role without one can't be used
+    let account = sts::account_id(&info.cloudwatch_logs_assume_role_arn)
+        .ok_or_else(|| RuntimeError::InvalidField("cloudwatch_logs_assume_role_arn".into()))?;
 
-    // Initialize CloudWatch logs client with assumed credentials
-    let cw_config = sts::assume_role(
+    let clients = role_clients(
         sts_client,
-        &session_id,
+        credentials,
+        account,
+        request_id,
         &info.cloudwatch_logs_assume_role_arn,
+        &info.assume_role_options(),
     )
     .await?;
-    let cw_client = CwClient::new(&cw_config);
 
-    // replace aws/lambda/... with our own log prefix
-    let new_log_group = format!("aws/amplify/compute/{}", &info.name);
-    create_new_log_group_if_missing(&cw_client, &new_log_group).await?;
+    // replace aws/lambda/... with our own log group
+    let template = info
+        .log_group_template
+        .as_ref()
+        .unwrap_or(&config.log_group_template);
+    let new_log_group = naming::log_group_name(template, &config.log_group_prefix, account, &info)?;
+    let settings = LogGroupSettings {
+        retention_in_days: info.retention_in_days.or(config.default_retention_in_days),
+        kms_key_id: info.kms_key_id.clone(),
+        tags: info.tags.clone(),
+    };
 
-    send_events(
-        &cw_client,
-        &new_log_group,
-        &data.log_stream,
-        &data.log_events,
-    )
-    .await
+    let strategy = info
+        .log_stream_strategy
+        .as_ref()
+        .unwrap_or(&config.log_stream_strategy);
+    let new_log_stream =
+        naming::log_stream_name(strategy, &data.log_stream, &info, normalize::now_millis())?;
+
+    let mut sinks: Vec<Box<dyn LogSink + 'a>> = vec![Box::new(CloudWatchLogsSink::new(
+        clients.cloudwatch_logs,
+        config,
+        cache,
+        Destination {
+            account: account.to_owned(),
+            log_group: new_log_group,
+            log_stream: new_log_stream,
+        },
+        settings,
+    ))];
+
+    if let Some(bucket) = &info.archive_bucket {
+        sinks.push(Box::new(S3Sink::new(
+            clients.s3,
+            ArchiveDestination {
+                account: account.to_owned(),
+                bucket: bucket.clone(),
+                prefix: function_id,
+            },
+        )));
+    }
+
+    Ok((info, sinks))
+}
+
+/// Initialize the clients of a role with assumed credentials,
+/// reusing the cached clients of the role when there are some.
+///
+/// The functions of a role share its session, so the session name has
+/// the tenant account rather than a function, and the id of the request
+/// that created the clients. Cached clients keep that request id while they
+/// refresh their credentials, so it identifies the container rather than each request.
+#[tracing::instrument(skip(sts_client, credentials, options))]
+async fn role_clients(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    account: &str,
+    request_id: &str,
+    role_arn: &str,
+    options: &sts::AssumeRoleOptions,
+) -> Result<RoleClients, RuntimeError> {
+    if let Some(clients) = credentials.clients(role_arn, options) {
+        return Ok(clients);
+    }
+
+    let session_name = sts::session_name(account, request_id);
+    let provider = sts::AssumeRoleProvider::new(sts_client, &session_name, role_arn)
+        .with_options(options.clone());
+    let clients = RoleClients::new(&sts::assumed_config(provider).await?);
+    credentials.insert(role_arn, options, clients.clone());
+    Ok(clients)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{event::AwsLogs, test_util::*};
+    use aws_smithy_client::test_connection::TestConnection;
+    use lambda_runtime::Context;
+
+    fn logs_event(message_type: &str) -> LambdaEvent<LogsEvent> {
+        let payload = LogsEvent {
+            aws_logs: AwsLogs {
+                data: log_data(message_type),
+            },
+        };
+        LambdaEvent::new(payload, Context::default())
+    }
+
+    #[tokio::test]
+    async fn test_handle_control_message() -> Result<(), RuntimeError> {
+        // AWS clients without connections fail if they are used
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let function_store = MemoryStore::default();
+
+        let report = handle_logs(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            logs_event("CONTROL_MESSAGE"),
+        )
+        .await?;
+        assert_eq!(InvocationReport::default(), report);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_unknown_message() {
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let function_store = MemoryStore::default();
+
+        let res = handle_logs(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            logs_event("OTHER_MESSAGE"),
+        )
+        .await;
+        assert!(matches!(res, Err(RuntimeError::UnknownMessageType(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_missing_function() -> Result<(), RuntimeError> {
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let function_store = MemoryStore::new([FunctionInfo {
+            id: "other".into(),
+            ..Default::default()
+        }]);
+
+        let report = handle_logs(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            logs_event("DATA_MESSAGE"),
+        )
+        .await?;
+
+        // the batch is dropped, as it would fail again
+        let reason = RuntimeError::MissingFunction("function".into()).to_string();
+        assert_eq!(Some(Outcome::Failed), report.outcome());
+        assert_eq!(Some(reason.as_str()), report.error());
+        assert!(!report.retried);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_transient_error() {
+        let sts_client = get_denied_sts_client(&get_mock_config().await, 1);
+        let function_store = MemoryStore::new([function_info()]);
+
+        let res = handle_logs(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            logs_event("DATA_MESSAGE"),
+        )
+        .await;
+        assert!(matches!(res, Err(RuntimeError::DeliveryFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_role_without_account() -> Result<(), RuntimeError> {
+        // the clients fail if they are used
+        let config = get_mock_config().await;
+        let function_store = MemoryStore::new([FunctionInfo {
+            id: "function".into(),
+            cloudwatch_logs_assume_role_arn: "logs".into(),
+            ..Default::default()
+        }]);
+
+        let report = handle_logs(
+            &StsClient::new(&config),
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            None,
+            logs_event("DATA_MESSAGE"),
+        )
+        .await?;
+
+        // the batch is dropped instead of delivered to a bucket or stream of no account
+        let reason = RuntimeError::InvalidField("cloudwatch_logs_assume_role_arn".into());
+        assert_eq!(Some(reason.to_string().as_str()), report.error());
+        assert!(!report.is_retryable());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_logs_with_retry_queue() -> Result<(), RuntimeError> {
+        // GIVEN a retry queue and a function whose role can't be assumed
+        let data = log_data("DATA_MESSAGE");
+        let reason = "failed to assume role";
+        let body = serde_json::to_string(&RetryMessage::new(&data, &[], reason, 1)).unwrap();
+        let conn = TestConnection::new(vec![send_message(&body, 30)]);
+        let config = get_mock_config().await;
+        let retry_queue = retry_queue(&config, &conn);
+
+        // WHEN the batch can't be delivered
+        let report = handle_logs(
+            &get_denied_sts_client(&config, 1),
+            &CredentialCache::new(),
+            &MemoryStore::new([function_info()]),
+            &Config::default(),
+            &StreamCache::new(),
+            Some(&retry_queue),
+            logs_event("DATA_MESSAGE"),
+        )
+        .await?;
+
+        // THEN the batch is sent to the retry queue
+        assert!(report.retried);
+        assert_eq!(Some(Outcome::Failed), report.outcome());
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_logs_with_permanent_error() -> Result<(), RuntimeError> {
+        // GIVEN a retry queue that fails if it's used, and a store without the function
+        let config = get_mock_config().await;
+        let retry_queue = RetryQueue::new(&config, "https://queue");
+
+        // WHEN the batch can't be delivered
+        let report = handle_logs(
+            &StsClient::new(&config),
+            &CredentialCache::new(),
+            &MemoryStore::default(),
+            &Config::default(),
+            &StreamCache::new(),
+            Some(&retry_queue),
+            logs_event("DATA_MESSAGE"),
+        )
+        .await?;
+
+        // THEN the batch is dropped instead of retried
+        assert!(!report.retried);
+        assert_eq!(Some(Outcome::Failed), report.outcome());
+
+        Ok(())
+    }
 }
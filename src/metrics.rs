@@ -0,0 +1,48 @@
+//! # Metrics in the CloudWatch Embedded Metric Format.
+//!
+//! Lambda sends everything that the function prints to CloudWatch Logs,
+//! which extracts the metrics from the log lines that follow this format.
+//! See <https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html>
+
+use crate::normalize::now_millis;
+use serde_json::{json, Value};
+
+/// Namespace where the processor metrics are published
+const NAMESPACE: &str = "CloudWatchLogsProcessor";
+
+/// Build the embedded metric document for a count metric
+fn count_document(name: &str, value: usize, timestamp: i64) -> Value {
+    json!({
+        "_aws": {
+            "Timestamp": timestamp,
+            "CloudWatchMetrics": [{
+                "Namespace": NAMESPACE,
+                "Dimensions": [[]],
+                "Metrics": [{"Name": name, "Unit": "Count"}],
+            }],
+        },
+        name: value,
+    })
+}
+
+/// Publish a count metric
+pub fn count(name: &str, value: usize) {
+    println!("{}", count_document(name, value, now_millis()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_count_document() {
+        let document = count_document("ControlMessages", 1, 1552518348220);
+
+        assert_eq!(1, document["ControlMessages"]);
+        assert_eq!(1552518348220i64, document["_aws"]["Timestamp"]);
+        let metric = &document["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(NAMESPACE, metric["Namespace"]);
+        assert_eq!("ControlMessages", metric["Metrics"][0]["Name"]);
+        assert_eq!("Count", metric["Metrics"][0]["Unit"]);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{error::RuntimeError, function_info::FunctionInfo, template::Template};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{collections::HashMap, str::FromStr};
+
+/// Prefix used in log group names when the environment doesn't set one
+pub const DEFAULT_LOG_GROUP_PREFIX: &str = "aws/amplify/compute";
+
+/// Template used for log group names when the environment doesn't set one
+pub const DEFAULT_LOG_GROUP_TEMPLATE: &str = "{prefix}/{function_name}";
+
+/// Variables available in log group templates
+pub const LOG_GROUP_VARIABLES: &[&str] = &[
+    "prefix",
+    "account",
+    "function_id",
+    "function_name",
+    "app_id",
+    "branch",
+];
+
+/// CloudWatch limit for log group names
+const MAX_LOG_GROUP_NAME_LEN: usize = 512;
+
+/// Parse a log group name template
+pub fn log_group_template(source: &str) -> Result<Template, RuntimeError> {
+    Template::parse(source, LOG_GROUP_VARIABLES)
+}
+
+/// Check that a log group name follows the CloudWatch naming rules.
+/// Names can be between 1 and 512 characters long, and they can only contain
+/// letters, numbers, '_', '-', '/', '.', and '#'.
+pub fn validate_log_group_name(name: &str) -> Result<(), RuntimeError> {
+    let invalid_char = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '/' | '.' | '#')));
+
+    match invalid_char {
+        _ if name.is_empty() => Err(RuntimeError::InvalidLogGroupName(
+            "log group name is empty".into(),
+        )),
+        _ if name.len() > MAX_LOG_GROUP_NAME_LEN => Err(RuntimeError::InvalidLogGroupName(
+            format!("{name} is longer than {MAX_LOG_GROUP_NAME_LEN} characters"),
+        )),
+        Some(c) => Err(RuntimeError::InvalidLogGroupName(format!(
+            "{name} contains the invalid character {c:?}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Render the name of the log group in the customer account for a function
+pub fn log_group_name(
+    template: &Template,
+    prefix: &str,
+    account: &str,
+    info: &FunctionInfo,
+) -> Result<String, RuntimeError> {
+    let mut variables = HashMap::from([
+        ("prefix", prefix),
+        ("account", account),
+        ("function_id", info.id.as_str()),
+        ("function_name", info.name.as_str()),
+    ]);
+    if let Some(app_id) = &info.app_id {
+        variables.insert("app_id", app_id);
+    }
+    if let Some(branch) = &info.branch {
+        variables.insert("branch", branch);
+    }
+
+    let name = template.render(&variables)?;
+    validate_log_group_name(&name)?;
+    Ok(name)
+}
+
+/// Variables available in log stream templates
+pub const LOG_STREAM_VARIABLES: &[&str] = &[
+    "source_stream",
+    "date",
+    "version",
+    "instance_id",
+    "instance_hash",
+    "function_id",
+    "function_name",
+];
+
+/// CloudWatch limit for log stream names
+const MAX_LOG_STREAM_NAME_LEN: usize = 512;
+
+/// `LogStreamStrategy` decides the name of the log stream in the customer account
+///
+/// Strategies are written as `passthrough`, `date`, `instance_hash`,
+/// `single:<stream name>` or `template:<stream template>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum LogStreamStrategy {
+    /// Use the name of the source stream, like `2022/06/09/[$LATEST]0123abcd`
+    #[default]
+    Passthrough,
+    /// One stream per day, like `2022/06/09`
+    DateBucketed,
+    /// One stream per day and function instance, like `2022/06/09/5d4c3b2a`
+    InstanceHash,
+    /// All the events go to the same stream
+    Single(String),
+    /// Stream name rendered from a template with the `LOG_STREAM_VARIABLES`
+    Templated(Template),
+}
+
+impl FromStr for LogStreamStrategy {
+    type Err = RuntimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "passthrough" => Ok(LogStreamStrategy::Passthrough),
+            None if s == "date" => Ok(LogStreamStrategy::DateBucketed),
+            None if s == "instance_hash" => Ok(LogStreamStrategy::InstanceHash),
+            Some(("single", name)) => {
+                validate_log_stream_name(name)?;
+                Ok(LogStreamStrategy::Single(name.to_owned()))
+            }
+            Some(("template", template)) => Ok(LogStreamStrategy::Templated(Template::parse(
+                template,
+                LOG_STREAM_VARIABLES,
+            )?)),
+            _ => Err(RuntimeError::InvalidConfig(format!(
+                "unknown log stream strategy {s}"
+            ))),
+        }
+    }
+}
+
+/// Format of the log stream names that Lambda creates
+static SOURCE_STREAM_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\d{4}/\d{2}/\d{2})/\[([^\]]*)\](.+)$").expect("invalid source stream regex")
+});
+
+/// Parts of the log stream names that Lambda creates,
+/// like `2022/06/09/[$LATEST]0123abcd`.
+struct SourceStream<'a> {
+    date: Option<&'a str>,
+    version: Option<&'a str>,
+    instance_id: Option<&'a str>,
+}
+
+impl<'a> SourceStream<'a> {
+    fn parse(name: &'a str) -> SourceStream<'a> {
+        match SOURCE_STREAM_REGEX.captures(name) {
+            Some(captures) => SourceStream {
+                date: captures.get(1).map(|m| m.as_str()),
+                version: captures.get(2).map(|m| m.as_str()),
+                instance_id: captures.get(3).map(|m| m.as_str()),
+            },
+            None => SourceStream {
+                date: None,
+                version: None,
+                instance_id: None,
+            },
+        }
+    }
+}
+
+/// Format a timestamp in milliseconds as a `YYYY/MM/DD` UTC date
+pub fn utc_date(millis: i64) -> String {
+    // Civil from days algorithm, from http://howardhinnant.github.io/date_algorithms.html
+    let days = millis.div_euclid(86_400_000) + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}/{month:02}/{day:02}")
+}
+
+/// Stable short hash of a function instance id, using FNV-1a
+fn instance_hash(instance_id: &str) -> String {
+    let hash = instance_id.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:08x}", hash as u32)
+}
+
+/// Check that a log stream name follows the CloudWatch naming rules.
+/// Names can be between 1 and 512 characters long, and they cannot contain ':' or '*'.
+pub fn validate_log_stream_name(name: &str) -> Result<(), RuntimeError> {
+    if name.is_empty() {
+        return Err(RuntimeError::InvalidLogStreamName(
+            "log stream name is empty".into(),
+        ));
+    }
+    if name.len() > MAX_LOG_STREAM_NAME_LEN {
+        return Err(RuntimeError::InvalidLogStreamName(format!(
+            "{name} is longer than {MAX_LOG_STREAM_NAME_LEN} characters"
+        )));
+    }
+    if name.contains([':', '*']) {
+        return Err(RuntimeError::InvalidLogStreamName(format!(
+            "{name} contains ':' or '*'"
+        )));
+    }
+    Ok(())
+}
+
+/// Render the name of the log stream in the customer account.
+/// When the source stream doesn't include a date, the current date is used.
+pub fn log_stream_name(
+    strategy: &LogStreamStrategy,
+    source_stream: &str,
+    info: &FunctionInfo,
+    now: i64,
+) -> Result<String, RuntimeError> {
+    let source = SourceStream::parse(source_stream);
+    let date = source
+        .date
+        .map(str::to_owned)
+        .unwrap_or_else(|| utc_date(now));
+    let hash = instance_hash(source.instance_id.unwrap_or(source_stream));
+
+    let name = match strategy {
+        LogStreamStrategy::Passthrough => source_stream.to_owned(),
+        LogStreamStrategy::DateBucketed => date,
+        LogStreamStrategy::InstanceHash => format!("{date}/{hash}"),
+        LogStreamStrategy::Single(name) => name.clone(),
+        LogStreamStrategy::Templated(template) => {
+            let mut variables = HashMap::from([
+                ("source_stream", source_stream),
+                ("date", date.as_str()),
+                ("instance_hash", hash.as_str()),
+                ("function_id", info.id.as_str()),
+                ("function_name", info.name.as_str()),
+            ]);
+            if let Some(version) = source.version {
+                variables.insert("version", version);
+            }
+            if let Some(instance_id) = source.instance_id {
+                variables.insert("instance_id", instance_id);
+            }
+            template.render(&variables)?
+        }
+    };
+
+    validate_log_stream_name(&name)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn function_info() -> FunctionInfo {
+        FunctionInfo {
+            id: "1".into(),
+            name: "app-id-1-branch-2".into(),
+            cloudwatch_logs_assume_role_arn: "arn".into(),
+            app_id: Some("app-id-1".into()),
+            branch: Some("branch-2".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_log_group_name() {
+        let template = log_group_template(DEFAULT_LOG_GROUP_TEMPLATE).unwrap();
+        let name = log_group_name(
+            &template,
+            DEFAULT_LOG_GROUP_PREFIX,
+            "123456789012",
+            &function_info(),
+        )
+        .unwrap();
+
+        assert_eq!("aws/amplify/compute/app-id-1-branch-2", name);
+    }
+
+    #[test]
+    fn test_custom_log_group_name() {
+        let template = log_group_template("/{prefix}/{app_id}/{branch}/{function_id}").unwrap();
+        let name = log_group_name(&template, "amplify", "123456789012", &function_info()).unwrap();
+
+        assert_eq!("/amplify/app-id-1/branch-2/1", name);
+    }
+
+    #[test]
+    fn test_log_group_name_with_missing_variable() {
+        let template = log_group_template("{prefix}/{branch}").unwrap();
+        let info = FunctionInfo {
+            branch: None,
+            ..function_info()
+        };
+
+        assert!(matches!(
+            log_group_name(&template, "amplify", "123456789012", &info),
+            Err(RuntimeError::InvalidTemplate(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_log_group_name() {
+        assert!(validate_log_group_name("aws/amplify/compute/app_1.main#2").is_ok());
+        assert!(validate_log_group_name("").is_err());
+        assert!(validate_log_group_name("with spaces").is_err());
+        assert!(validate_log_group_name("with:colon").is_err());
+        assert!(validate_log_group_name(&"a".repeat(MAX_LOG_GROUP_NAME_LEN + 1)).is_err());
+    }
+
+    const SOURCE_STREAM: &str = "2019/03/13/[$LATEST]94fa867e5374431291a7fc14e2f56ae7";
+
+    // 2022/06/09 12:00:00 UTC
+    const NOW: i64 = 1_654_776_000_000;
+
+    #[test]
+    fn test_log_stream_strategies() {
+        let info = function_info();
+        let name = |strategy: &str| {
+            log_stream_name(&strategy.parse().unwrap(), SOURCE_STREAM, &info, NOW).unwrap()
+        };
+
+        assert_eq!(SOURCE_STREAM, name("passthrough"));
+        assert_eq!("2019/03/13", name("date"));
+        assert_eq!(
+            format!(
+                "2019/03/13/{}",
+                instance_hash("94fa867e5374431291a7fc14e2f56ae7")
+            ),
+            name("instance_hash")
+        );
+        assert_eq!("app", name("single:app"));
+        assert_eq!(
+            "app-id-1-branch-2/2019/03/13/$LATEST",
+            name("template:{function_name}/{date}/{version}")
+        );
+    }
+
+    #[test]
+    fn test_log_stream_name_without_lambda_source_stream() {
+        let strategy = LogStreamStrategy::DateBucketed;
+        let name = log_stream_name(&strategy, "custom", &function_info(), NOW).unwrap();
+
+        assert_eq!("2022/06/09", name);
+    }
+
+    #[test]
+    fn test_invalid_log_stream_strategies() {
+        assert!("unknown".parse::<LogStreamStrategy>().is_err());
+        assert!("single:".parse::<LogStreamStrategy>().is_err());
+        assert!("single:a:b".parse::<LogStreamStrategy>().is_err());
+        assert!("template:{unknown}".parse::<LogStreamStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!("1970/01/01", utc_date(0));
+        assert_eq!("2022/06/09", utc_date(NOW));
+        assert_eq!("2020/02/29", utc_date(1_582_977_600_000));
+    }
+
+    #[test]
+    fn test_instance_hash_is_stable() {
+        assert_eq!(instance_hash("instance"), instance_hash("instance"));
+        assert_ne!(instance_hash("instance"), instance_hash("other"));
+        assert_eq!(8, instance_hash("instance").len());
+    }
+}
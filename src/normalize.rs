@@ -0,0 +1,184 @@
+use crate::{error::RuntimeError, event::LogEntry};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// CloudWatch rejects events older than 14 days.
+pub const MAX_EVENT_AGE_MILLIS: i64 = 14 * 24 * 60 * 60 * 1000;
+
+/// CloudWatch rejects events more than 2 hours in the future.
+pub const MAX_EVENT_FUTURE_MILLIS: i64 = 2 * 60 * 60 * 1000;
+
+/// Safety margin applied to clamped timestamps, so they are still
+/// accepted by the time they reach CloudWatch.
+const CLAMP_MARGIN_MILLIS: i64 = 60 * 1000;
+
+/// What to do with events outside of the CloudWatch acceptance window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfRangePolicy {
+    /// Remove the events from the batch
+    #[default]
+    Drop,
+    /// Move the event timestamp to the closest accepted time
+    Clamp,
+}
+
+impl FromStr for OutOfRangePolicy {
+    type Err = RuntimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "drop" => Ok(OutOfRangePolicy::Drop),
+            "clamp" => Ok(OutOfRangePolicy::Clamp),
+            _ => Err(RuntimeError::InvalidConfig(format!(
+                "unknown out of range policy {s}"
+            ))),
+        }
+    }
+}
+
+/// Counts of the adjustments made to a batch before delivery
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NormalizeReport {
+    /// Number of events that arrived out of chronological order
+    pub reordered: usize,
+    /// Number of events removed for being outside the acceptance window
+    pub dropped: usize,
+    /// Number of events whose timestamp was moved inside the acceptance window
+    pub clamped: usize,
+}
+
+/// Current time in milliseconds since the Unix epoch, like CloudWatch timestamps
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Prepare a list of events to be accepted by PutLogEvents.
+///
+/// Events outside of the acceptance window, relative to `now` in milliseconds,
+/// are dropped or clamped depending on the policy.
+/// The remaining events are stably sorted by timestamp.
+pub fn normalize_events<'a>(
+    events: Vec<&'a LogEntry>,
+    now: i64,
+    policy: OutOfRangePolicy,
+) -> (Vec<Cow<'a, LogEntry>>, NormalizeReport) {
+    let oldest = now - MAX_EVENT_AGE_MILLIS;
+    let newest = now + MAX_EVENT_FUTURE_MILLIS;
+
+    let mut report = NormalizeReport::default();
+    let mut normalized = Vec::with_capacity(events.len());
+
+    for event in events {
+        if (oldest..=newest).contains(&event.timestamp) {
+            normalized.push(Cow::Borrowed(event));
+            continue;
+        }
+
+        match policy {
+            OutOfRangePolicy::Drop => report.dropped += 1,
+            OutOfRangePolicy::Clamp => {
+                let mut event = event.clone();
+                event.timestamp = event
+                    .timestamp
+                    .clamp(oldest + CLAMP_MARGIN_MILLIS, newest - CLAMP_MARGIN_MILLIS);
+                normalized.push(Cow::Owned(event));
+                report.clamped += 1;
+            }
+        }
+    }
+
+    let mut latest = i64::MIN;
+    for event in &normalized {
+        if event.timestamp < latest {
+            report.reordered += 1;
+        }
+        latest = latest.max(event.timestamp);
+    }
+
+    if report.reordered > 0 {
+        normalized.sort_by_key(|e| e.timestamp);
+    }
+
+    (normalized, report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NOW: i64 = 1_655_000_000_000;
+
+    fn entry(id: &str, timestamp: i64) -> LogEntry {
+        LogEntry {
+            id: id.into(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_normalize_sorts_events() {
+        let entries = [entry("1", NOW), entry("2", NOW - 10), entry("3", NOW)];
+        let (events, report) =
+            normalize_events(entries.iter().collect(), NOW, OutOfRangePolicy::Drop);
+
+        let ids: Vec<&str> = events.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(vec!["2", "1", "3"], ids);
+        assert_eq!(1, report.reordered);
+        assert_eq!(0, report.dropped);
+    }
+
+    #[test]
+    fn test_normalize_drops_out_of_range_events() {
+        let entries = [
+            entry("1", NOW - MAX_EVENT_AGE_MILLIS - 1),
+            entry("2", NOW),
+            entry("3", NOW + MAX_EVENT_FUTURE_MILLIS + 1),
+        ];
+        let (events, report) =
+            normalize_events(entries.iter().collect(), NOW, OutOfRangePolicy::Drop);
+
+        assert_eq!(1, events.len());
+        assert_eq!("2", events[0].id);
+        assert_eq!(2, report.dropped);
+        assert_eq!(0, report.clamped);
+    }
+
+    #[test]
+    fn test_normalize_clamps_out_of_range_events() {
+        let entries = [
+            entry("1", NOW + MAX_EVENT_FUTURE_MILLIS + 1),
+            entry("2", NOW),
+            entry("3", NOW - MAX_EVENT_AGE_MILLIS - 1),
+        ];
+        let (events, report) =
+            normalize_events(entries.iter().collect(), NOW, OutOfRangePolicy::Clamp);
+
+        let ids: Vec<&str> = events.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(vec!["3", "2", "1"], ids);
+        assert_eq!(
+            NOW - MAX_EVENT_AGE_MILLIS + CLAMP_MARGIN_MILLIS,
+            events[0].timestamp
+        );
+        assert_eq!(
+            NOW + MAX_EVENT_FUTURE_MILLIS - CLAMP_MARGIN_MILLIS,
+            events[2].timestamp
+        );
+        assert_eq!(2, report.clamped);
+        assert_eq!(0, report.dropped);
+    }
+
+    #[test]
+    fn test_out_of_range_policy_from_str() {
+        assert_eq!(OutOfRangePolicy::Drop, "drop".parse().unwrap());
+        assert_eq!(OutOfRangePolicy::Clamp, "CLAMP".parse().unwrap());
+        assert!("other".parse::<OutOfRangePolicy>().is_err());
+    }
+}
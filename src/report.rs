@@ -0,0 +1,413 @@
+use crate::{event::LogEntry, normalize::NormalizeReport, sink::SinkDestination};
+use aws_sdk_cloudwatchlogs::model::RejectedLogEventsInfo;
+use serde::Serialize;
+use std::{borrow::Borrow, collections::HashSet};
+
+/// Summary of the events delivered to the customer account
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DeliveryReport {
+    /// Number of PutLogEvents calls performed
+    pub batches: usize,
+    /// Number of events delivered
+    pub events: usize,
+    /// Number of events skipped by the log filter
+    pub filtered: usize,
+    /// Adjustments made to the events before delivery
+    pub normalized: NormalizeReport,
+    /// Number of events dropped for exceeding the CloudWatch event size limit
+    pub oversized: usize,
+    /// Events that CloudWatch didn't accept
+    pub rejected: Vec<RejectedEvent>,
+    /// Differences between the log group and the function settings
+    pub drift: Vec<LogGroupDrift>,
+    /// Ids of the events in the calls that succeeded, rejected events included,
+    /// so an error only retries the rest of the batch
+    #[serde(skip)]
+    pub sent_ids: HashSet<String>,
+    /// Ids of the events that were never submitted: skipped by the filter,
+    /// dropped by the normalization or too large, so they are not retried
+    #[serde(skip)]
+    pub skipped_ids: HashSet<String>,
+}
+
+/// Summary of an invocation, with the result of each destination
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct InvocationReport {
+    /// Result of the delivery to each destination
+    pub destinations: Vec<DestinationReport>,
+    /// Whether the events were sent to the retry queue
+    pub retried: bool,
+}
+
+impl InvocationReport {
+    /// Combined outcome of the destinations, or None if there was nothing to deliver
+    pub fn outcome(&self) -> Option<Outcome> {
+        let mut outcomes = self.destinations.iter().map(DestinationReport::outcome);
+        let first = outcomes.next()?;
+        if outcomes.all(|outcome| outcome == first) {
+            Some(first)
+        } else {
+            Some(Outcome::Partial)
+        }
+    }
+
+    /// Check if a destination failed with an error that may be fixed
+    /// by delivering the events again
+    pub fn is_retryable(&self) -> bool {
+        self.destinations
+            .iter()
+            .any(DestinationReport::is_retryable)
+    }
+
+    /// Error of the first destination that failed
+    pub fn error(&self) -> Option<&str> {
+        self.destinations
+            .iter()
+            .find_map(|destination| destination.error.as_deref())
+    }
+
+    /// Events that a destination accepted, in their original order.
+    /// Events skipped by the filter, dropped by the normalization
+    /// or rejected everywhere are left out.
+    pub fn delivered(&self, log_events: &[LogEntry]) -> Vec<LogEntry> {
+        log_events
+            .iter()
+            .filter(|event| {
+                self.destinations.iter().any(|destination| {
+                    let delivery = &destination.delivery;
+                    delivery.sent_ids.contains(&event.id)
+                        && !delivery.rejected.iter().any(|r| r.id == event.id)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// `Outcome` is the result of the delivery to a destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// All the events were processed
+    Delivered,
+    /// Some events were delivered before an error
+    Partial,
+    /// No events were delivered because of an error
+    Failed,
+}
+
+/// Result of the delivery of a log stream batch to its destination
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DestinationReport {
+    /// Log group where the events were published
+    pub source_log_group: String,
+    /// Log stream where the events were published
+    pub source_log_stream: String,
+    /// Destination in the customer account, if it could be resolved
+    pub destination: Option<SinkDestination>,
+    /// Events delivered, filtered and rejected
+    #[serde(flatten)]
+    pub delivery: DeliveryReport,
+    /// Error that stopped the delivery
+    pub error: Option<String>,
+    /// Whether delivering the events again may fix the error
+    pub retryable: bool,
+}
+
+impl DestinationReport {
+    /// Outcome of the delivery
+    pub fn outcome(&self) -> Outcome {
+        match &self.error {
+            None => Outcome::Delivered,
+            Some(_) if self.delivery.batches > 0 => Outcome::Partial,
+            Some(_) => Outcome::Failed,
+        }
+    }
+
+    /// Check if the delivery failed with an error that may be fixed
+    /// by delivering the events again
+    pub fn is_retryable(&self) -> bool {
+        self.error.is_some() && self.retryable
+    }
+
+    /// Events submitted to the destination that it didn't receive before its error,
+    /// the part of the batch that must be delivered again
+    pub fn undelivered(&self, log_events: &[LogEntry]) -> Vec<LogEntry> {
+        let delivery = &self.delivery;
+        log_events
+            .iter()
+            .filter(|event| {
+                !delivery.sent_ids.contains(&event.id) && !delivery.skipped_ids.contains(&event.id)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Response for event sources that support partial batch failures,
+/// like Kinesis and SQS. Lambda retries only the failed records.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResponse {
+    /// Records that could not be processed
+    pub batch_item_failures: Vec<BatchItemFailure>,
+}
+
+/// `BatchItemFailure` identifies a record that must be retried
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemFailure {
+    /// Sequence number of a Kinesis record, or message id of an SQS message
+    pub item_identifier: String,
+}
+
+/// `LogGroupDrift` is a difference between an existing log group
+/// and the settings of the function
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "setting")]
+pub enum LogGroupDrift {
+    /// The log group is not encrypted with the expected KMS key
+    KmsKeyId {
+        /// KMS key in the function settings
+        expected: Option<String>,
+        /// KMS key in the log group
+        actual: Option<String>,
+    },
+    /// A tag in the log group doesn't have the expected value
+    Tag {
+        /// Tag name
+        key: String,
+        /// Tag value in the function settings
+        expected: String,
+        /// Tag value in the log group
+        actual: Option<String>,
+    },
+}
+
+/// Reason why CloudWatch rejected an event
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// The event is older than the log group retention period
+    Expired,
+    /// The event is older than 14 days
+    TooOld,
+    /// The event is more than 2 hours in the future
+    TooNew,
+}
+
+/// `RejectedEvent` identifies an event that CloudWatch didn't accept
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RejectedEvent {
+    /// Unique id of the original entry
+    pub id: String,
+    /// Reason why the event was rejected
+    pub reason: RejectionReason,
+}
+
+/// Map the rejected indexes in a PutLogEvents response to the events in the batch.
+///
+/// The too old and expired indexes are exclusive,
+/// the too new index is inclusive.
+pub fn rejected_events<T: Borrow<LogEntry>>(
+    batch: &[T],
+    info: &RejectedLogEventsInfo,
+) -> Vec<RejectedEvent> {
+    let index = |i: Option<i32>| i.map(|i| i.max(0) as usize);
+    let expired_end = index(info.expired_log_event_end_index).unwrap_or(0);
+    let too_old_end = index(info.too_old_log_event_end_index).unwrap_or(0);
+    let too_new_start = index(info.too_new_log_event_start_index).unwrap_or(batch.len());
+
+    batch
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, event)| {
+            let reason = if idx < expired_end {
+                RejectionReason::Expired
+            } else if idx < too_old_end {
+                RejectionReason::TooOld
+            } else if idx >= too_new_start {
+                RejectionReason::TooNew
+            } else {
+                return None;
+            };
+
+            Some(RejectedEvent {
+                id: event.borrow().id.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn batch() -> Vec<LogEntry> {
+        (0..5)
+            .map(|i| LogEntry {
+                id: i.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn destination_report(batches: usize, error: Option<&str>) -> DestinationReport {
+        DestinationReport {
+            delivery: DeliveryReport {
+                batches,
+                ..Default::default()
+            },
+            error: error.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_destination_outcome() {
+        assert_eq!(Outcome::Delivered, destination_report(0, None).outcome());
+        assert_eq!(
+            Outcome::Partial,
+            destination_report(1, Some("throttled")).outcome()
+        );
+        assert_eq!(
+            Outcome::Failed,
+            destination_report(0, Some("throttled")).outcome()
+        );
+    }
+
+    #[test]
+    fn test_invocation_outcome() {
+        let report = |destinations| InvocationReport {
+            destinations,
+            retried: false,
+        };
+
+        assert_eq!(None, report(vec![]).outcome());
+        assert_eq!(
+            Some(Outcome::Delivered),
+            report(vec![destination_report(1, None)]).outcome()
+        );
+        assert_eq!(
+            Some(Outcome::Failed),
+            report(vec![destination_report(0, Some("error")); 2]).outcome()
+        );
+        assert_eq!(
+            Some(Outcome::Partial),
+            report(vec![
+                destination_report(1, None),
+                destination_report(0, Some("error"))
+            ])
+            .outcome()
+        );
+    }
+
+    #[test]
+    fn test_invocation_error() {
+        let report = InvocationReport {
+            destinations: vec![
+                destination_report(1, None),
+                destination_report(0, Some("throttled")),
+            ],
+            retried: false,
+        };
+        assert_eq!(Some("throttled"), report.error());
+        assert_eq!(None, InvocationReport::default().error());
+        assert!(!report.is_retryable());
+
+        let mut report = report;
+        report.destinations[1].retryable = true;
+        assert!(report.is_retryable());
+    }
+
+    #[test]
+    fn test_delivered() {
+        let report = InvocationReport {
+            destinations: vec![
+                DestinationReport {
+                    delivery: DeliveryReport {
+                        sent_ids: ["0".to_string(), "1".to_string()].into(),
+                        rejected: vec![RejectedEvent {
+                            id: "0".into(),
+                            reason: RejectionReason::TooOld,
+                        }],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                DestinationReport {
+                    delivery: DeliveryReport {
+                        sent_ids: ["3".to_string()].into(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ],
+            retried: false,
+        };
+
+        let ids: Vec<String> = report
+            .delivered(&batch())
+            .into_iter()
+            .map(|event| event.id)
+            .collect();
+        assert_eq!(vec!["1", "3"], ids);
+        assert!(InvocationReport::default().delivered(&batch()).is_empty());
+    }
+
+    #[test]
+    fn test_undelivered() {
+        let report = DestinationReport {
+            delivery: DeliveryReport {
+                batches: 1,
+                sent_ids: ["0".to_string(), "1".to_string()].into(),
+                skipped_ids: ["3".to_string()].into(),
+                ..Default::default()
+            },
+            error: Some("throttled".into()),
+            ..Default::default()
+        };
+
+        let ids: Vec<String> = report
+            .undelivered(&batch())
+            .into_iter()
+            .map(|event| event.id)
+            .collect();
+        assert_eq!(vec!["2", "4"], ids);
+    }
+
+    #[test]
+    fn test_rejected_events_empty_info() {
+        let info = RejectedLogEventsInfo::builder().build();
+        assert!(rejected_events(&batch(), &info).is_empty());
+    }
+
+    #[test]
+    fn test_rejected_events() {
+        let info = RejectedLogEventsInfo::builder()
+            .expired_log_event_end_index(1)
+            .too_old_log_event_end_index(2)
+            .too_new_log_event_start_index(4)
+            .build();
+
+        let rejected = rejected_events(&batch(), &info);
+        assert_eq!(
+            vec![
+                RejectedEvent {
+                    id: "0".into(),
+                    reason: RejectionReason::Expired
+                },
+                RejectedEvent {
+                    id: "1".into(),
+                    reason: RejectionReason::TooOld
+                },
+                RejectedEvent {
+                    id: "4".into(),
+                    reason: RejectionReason::TooNew
+                },
+            ],
+            rejected
+        );
+    }
+}
@@ -0,0 +1,652 @@
+use crate::{
+    deliver_log_data,
+    error::RuntimeError,
+    event::LogData,
+    function_store::FunctionInfoStore,
+    metrics,
+    report::{BatchItemFailure, BatchResponse, InvocationReport, Outcome},
+    sink::SinkKind,
+    Config, CredentialCache, StreamCache,
+};
+use aws_sdk_sqs::{Client, Error};
+use aws_sdk_sts::Client as StsClient;
+use lambda_runtime::LambdaEvent;
+use serde::{Deserialize, Serialize};
+
+/// `RetryMessage` is a batch of events that could not be delivered,
+/// as it's stored in the retry queue
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RetryMessage {
+    /// Number of times that the delivery failed
+    pub attempt: u32,
+    /// Error of the last delivery
+    pub reason: String,
+    /// Sinks where the events must be delivered, all of them when it's empty
+    #[serde(default)]
+    pub sinks: Vec<SinkKind>,
+    /// Base64 gzipped log data, in the same format that CloudWatch sends
+    pub data: String,
+}
+
+impl RetryMessage {
+    /// Create a message for a batch of events
+    pub fn new(data: &LogData, sinks: &[SinkKind], reason: &str, attempt: u32) -> RetryMessage {
+        RetryMessage {
+            attempt,
+            reason: reason.to_owned(),
+            sinks: sinks.to_vec(),
+            data: data.encode(),
+        }
+    }
+}
+
+/// Maximum size of an SQS message body
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+
+/// Number of failed deliveries after which the rest of a batch is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before a batch is delivered again, doubling with each attempt
+/// up to the SQS limit of 15 minutes
+fn retry_delay(attempt: u32) -> i32 {
+    (30 << attempt.saturating_sub(1).min(5)).min(900)
+}
+
+/// Serialize the retry messages of a batch of events.
+/// Batches that don't fit in a single message are split in halves,
+/// until every message fits. Events that don't fit in a message on their own
+/// are left out, and their number is returned with the messages.
+fn retry_bodies(
+    data: &LogData,
+    sinks: &[SinkKind],
+    reason: &str,
+    attempt: u32,
+    max_size: usize,
+) -> (Vec<String>, usize) {
+    let message = RetryMessage::new(data, sinks, reason, attempt);
+    let body = serde_json::to_string(&message).expect("failed to serialize retry message");
+    if body.len() <= max_size {
+        return (vec![body], 0);
+    }
+    if data.log_events.len() <= 1 {
+        return (vec![], data.log_events.len());
+    }
+
+    let (first, second) = data.log_events.split_at(data.log_events.len() / 2);
+    let mut bodies = Vec::new();
+    let mut oversized = 0;
+    for events in [first, second] {
+        let (half, dropped) = retry_bodies(
+            &data.with_events(events.to_vec()),
+            sinks,
+            reason,
+            attempt,
+            max_size,
+        );
+        bodies.extend(half);
+        oversized += dropped;
+    }
+    (bodies, oversized)
+}
+
+/// `Settlement` is what happened to the events that a delivery left behind
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Settlement {
+    /// Every destination received its events
+    Delivered,
+    /// The undelivered events were dropped
+    Dropped,
+    /// The undelivered events were sent to the retry queue
+    Retried,
+    /// Nothing was delivered, so the source must send the batch again
+    Failed(String),
+}
+
+/// Apply the failure policy to a batch after its `attempt`-th delivery,
+/// the same for every event source:
+/// - batches that fail with permanent errors, like missing functions, are dropped,
+///   as they would fail again.
+/// - with a retry queue, the events that each destination didn't receive are sent
+///   to the queue, to be delivered again only to the sinks that failed,
+///   until the batch failed `MAX_ATTEMPTS` times.
+/// - without a queue, or when it can't be reached, batches where nothing was delivered
+///   fail, so the source sends them again, and the rest of a partial delivery is dropped,
+///   as retrying the whole batch would duplicate the delivered events.
+pub(crate) async fn settle_delivery(
+    data: &LogData,
+    report: &InvocationReport,
+    retry_queue: Option<&RetryQueue>,
+    attempt: u32,
+) -> Settlement {
+    let log_group = &data.log_group;
+    let error = match report.error() {
+        Some(error) => error,
+        None => return Settlement::Delivered,
+    };
+
+    if !report.is_retryable() {
+        tracing::error!(%log_group, "dropping batch that can't be delivered: {error}");
+        metrics::count("DroppedBatches", 1);
+        return Settlement::Dropped;
+    }
+
+    if let Some(retry_queue) = retry_queue {
+        if attempt > MAX_ATTEMPTS {
+            tracing::error!(%log_group, attempt, "dropping batch that failed too many times: {error}");
+            metrics::count("ExhaustedBatches", 1);
+            return Settlement::Dropped;
+        }
+
+        tracing::warn!(%log_group, attempt, "failed to deliver batch, sending it to the retry queue: {error}");
+        match retry_queue.enqueue_undelivered(data, report, attempt).await {
+            Ok(()) => return Settlement::Retried,
+            Err(err) => {
+                tracing::error!(%log_group, "failed to send batch to the retry queue: {err}")
+            }
+        }
+    }
+
+    if report.outcome() == Some(Outcome::Failed) {
+        return Settlement::Failed(error.to_owned());
+    }
+    tracing::error!(%log_group, "dropping the rest of a partial delivery: {error}");
+    metrics::count("DroppedBatches", 1);
+    Settlement::Dropped
+}
+
+/// `RetryQueue` sends the batches of events that could not be delivered
+/// to an SQS queue, so they can be replayed later by the queue consumer.
+///
+/// The queue should have a redrive policy, so messages that can't be decoded
+/// or sent back to the queue are moved to a dead-letter queue.
+#[derive(Clone, Debug)]
+pub struct RetryQueue {
+    client: Client,
+    queue_url: String,
+}
+
+impl RetryQueue {
+    /// Initialize the retry queue.
+    #[tracing::instrument(skip(config))]
+    pub fn new(config: &aws_types::SdkConfig, queue_url: &str) -> RetryQueue {
+        tracing::info!("Initializing SQS client");
+        RetryQueue::from_client(Client::new(config), queue_url)
+    }
+
+    /// Create a retry queue that sends its messages with the given client.
+    pub fn from_client(client: Client, queue_url: &str) -> RetryQueue {
+        RetryQueue {
+            client,
+            queue_url: queue_url.into(),
+        }
+    }
+
+    /// Send the events that each destination with a retryable error didn't receive,
+    /// to be delivered again only to the sink of that destination.
+    pub async fn enqueue_undelivered(
+        &self,
+        data: &LogData,
+        report: &InvocationReport,
+        attempt: u32,
+    ) -> Result<(), RuntimeError> {
+        for destination in report.destinations.iter().filter(|d| d.is_retryable()) {
+            // destinations that could not be resolved are retried in every sink
+            let sinks: Vec<SinkKind> = destination.destination.iter().map(|d| d.kind()).collect();
+            let undelivered = data.with_events(destination.undelivered(&data.log_events));
+            let reason = destination.error.as_deref().unwrap_or_default();
+            self.enqueue(&undelivered, &sinks, reason, attempt).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a batch of events to the queue, delayed according to its attempt.
+    /// Batches larger than the SQS message limit are sent in several messages.
+    #[tracing::instrument(skip(self, data))]
+    pub async fn enqueue(
+        &self,
+        data: &LogData,
+        sinks: &[SinkKind],
+        reason: &str,
+        attempt: u32,
+    ) -> Result<(), RuntimeError> {
+        let (bodies, oversized) = retry_bodies(data, sinks, reason, attempt, MAX_MESSAGE_SIZE);
+        if oversized > 0 {
+            tracing::error!(oversized, "dropping events too large for a retry message");
+            metrics::count("OversizedRetryEvents", oversized);
+        }
+        if bodies.len() > 1 {
+            tracing::warn!(
+                messages = bodies.len(),
+                "splitting batch in several messages"
+            );
+        }
+
+        for body in bodies {
+            self.client
+                .send_message()
+                .queue_url(&self.queue_url)
+                .message_body(body)
+                .delay_seconds(retry_delay(attempt))
+                .send()
+                .await
+                .map_err(Error::from)?;
+        }
+
+        metrics::count("RetriedBatches", 1);
+        Ok(())
+    }
+}
+
+/// `SqsEvent` is the batch of messages that Lambda reads from an SQS queue
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct SqsEvent {
+    /// Messages in the batch
+    #[serde(rename = "Records")]
+    pub records: Vec<SqsMessage>,
+}
+
+/// `SqsMessage` is a message in an SQS batch
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SqsMessage {
+    /// Id of the message in the queue
+    pub message_id: String,
+    /// Message body, a JSON `RetryMessage`
+    pub body: String,
+}
+
+/// Decode a message from the retry queue and deliver its events again,
+/// following the failure policy of `settle_delivery` for the next attempt.
+#[allow(clippy::too_many_arguments)]
+async fn replay_message<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    retry_queue: &RetryQueue,
+    request_id: &str,
+    message: &SqsMessage,
+) -> Result<(), RuntimeError> {
+    let retry: RetryMessage = serde_json::from_str(&message.body)
+        .map_err(|e| RuntimeError::InvalidPayload(e.to_string()))?;
+    tracing::info!(
+        message_id = %message.message_id,
+        attempt = retry.attempt,
+        reason = %retry.reason,
+        "replaying failed batch"
+    );
+
+    let data = LogData::decode(&retry.data)?;
+    let report = deliver_log_data(
+        sts_client,
+        credentials,
+        function_store,
+        config,
+        cache,
+        request_id,
+        &data,
+        &retry.sinks,
+    )
+    .await;
+
+    match settle_delivery(&data, &report, Some(retry_queue), retry.attempt + 1).await {
+        Settlement::Failed(error) => Err(RuntimeError::DeliveryFailed(error)),
+        _ => Ok(()),
+    }
+}
+
+/// `handle_retry_messages` is the Lambda function entry point
+/// that consumes the retry queue.
+///
+/// Messages that fail again are sent back to the queue with the next attempt,
+/// and the rest of the messages that failed `MAX_ATTEMPTS` times is dropped.
+/// Messages that can't be decoded, or where nothing was delivered and that
+/// can't be sent back, are returned as batch item failures, so SQS makes them
+/// visible again until the redrive policy moves them to the dead-letter queue.
+#[tracing::instrument(skip(
+    sts_client,
+    credentials,
+    function_store,
+    config,
+    cache,
+    retry_queue,
+    event
+))]
+pub async fn handle_retry_messages<S: FunctionInfoStore + ?Sized>(
+    sts_client: &StsClient,
+    credentials: &CredentialCache,
+    function_store: &S,
+    config: &Config,
+    cache: &StreamCache,
+    retry_queue: &RetryQueue,
+    event: LambdaEvent<SqsEvent>,
+) -> Result<BatchResponse, RuntimeError> {
+    let request_id = event.context.request_id;
+    let mut response = BatchResponse::default();
+
+    for message in event.payload.records {
+        let res = replay_message(
+            sts_client,
+            credentials,
+            function_store,
+            config,
+            cache,
+            retry_queue,
+            &request_id,
+            &message,
+        )
+        .await;
+
+        if let Err(err) = res {
+            tracing::error!(message_id = %message.message_id, "failed to replay batch: {err}");
+            response.batch_item_failures.push(BatchItemFailure {
+                item_identifier: message.message_id,
+            });
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        event::LogEntry,
+        function_info::FunctionInfo,
+        function_store::MemoryStore,
+        report::{DeliveryReport, DestinationReport},
+        s3::ArchiveDestination,
+        sink::SinkDestination,
+        test_util::*,
+        Destination,
+    };
+    use aws_smithy_client::test_connection::TestConnection;
+    use aws_smithy_http::body::SdkBody;
+    use lambda_runtime::Context;
+
+    fn message(message_id: &str, body: String) -> SqsMessage {
+        SqsMessage {
+            message_id: message_id.into(),
+            body,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(30, retry_delay(1));
+        assert_eq!(60, retry_delay(2));
+        assert_eq!(480, retry_delay(5));
+        assert_eq!(900, retry_delay(6));
+        assert_eq!(900, retry_delay(u32::MAX));
+    }
+
+    #[test]
+    fn test_retry_message() {
+        let message = RetryMessage::new(&log_data("DATA_MESSAGE"), &[SinkKind::S3], "throttled", 1);
+        let json = serde_json::to_string(&message).unwrap();
+
+        let message: RetryMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(1, message.attempt);
+        assert_eq!("throttled", message.reason);
+        assert_eq!(vec![SinkKind::S3], message.sinks);
+        assert_eq!(
+            log_data("DATA_MESSAGE"),
+            LogData::decode(&message.data).unwrap()
+        );
+
+        // messages without sinks are delivered to all of them
+        let message: RetryMessage =
+            serde_json::from_str(r#"{"attempt": 1, "reason": "throttled", "data": ""}"#).unwrap();
+        assert!(message.sinks.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_sqs_event() {
+        let json = r#"{"Records": [{"messageId": "1", "receiptHandle": "handle",
+            "body": "{}", "attributes": {"ApproximateReceiveCount": "1"},
+            "messageAttributes": {}, "md5OfBody": "md5", "eventSource": "aws:sqs",
+            "eventSourceARN": "arn", "awsRegion": "us-east-1"}]}"#;
+
+        let event: SqsEvent = serde_json::from_str(json).expect("failed to deserialize");
+        assert_eq!(1, event.records.len());
+        assert_eq!("1", event.records[0].message_id);
+        assert_eq!("{}", event.records[0].body);
+    }
+
+    #[test]
+    fn test_retry_bodies() {
+        // events with random messages, that gzip can't compress much
+        let mut seed = 1u64;
+        let mut random = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            format!("{seed:016x}")
+        };
+        let events: Vec<LogEntry> = (0..64)
+            .map(|i| LogEntry {
+                id: i.to_string(),
+                timestamp: i,
+                message: (0..16).map(|_| random()).collect(),
+            })
+            .collect();
+        let data = log_data("DATA_MESSAGE").with_events(events.clone());
+
+        let (bodies, oversized) = retry_bodies(&data, &[], "error", 1, MAX_MESSAGE_SIZE);
+        assert_eq!((1, 0), (bodies.len(), oversized));
+
+        let (bodies, oversized) = retry_bodies(&data, &[], "error", 1, 4096);
+        assert!(bodies.len() > 1);
+        assert_eq!(0, oversized);
+        let mut replayed = Vec::new();
+        for body in &bodies {
+            assert!(body.len() <= 4096);
+            let message: RetryMessage = serde_json::from_str(body).unwrap();
+            replayed.extend(LogData::decode(&message.data).unwrap().log_events);
+        }
+        assert_eq!(events, replayed);
+
+        // an event that doesn't fit in a message on its own is left out
+        let mut events = events[..2].to_vec();
+        events[1].message = (0..512).map(|_| random()).collect();
+        let data = data.with_events(events.clone());
+        let (bodies, oversized) = retry_bodies(&data, &[], "error", 1, 4096);
+        assert_eq!(1, oversized);
+        assert_eq!(1, bodies.len());
+        let message: RetryMessage = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(
+            events[..1].to_vec(),
+            LogData::decode(&message.data).unwrap().log_events
+        );
+    }
+
+    #[tokio::test]
+    async fn test_settle_delivery_when_the_queue_fails() {
+        // GIVEN a retry queue that denies the messages
+        let data = log_data("DATA_MESSAGE");
+        let denied = || {
+            let (request, _) = send_message(
+                &serde_json::to_string(&RetryMessage::new(&data, &[], "throttled", 1)).unwrap(),
+                30,
+            );
+            let response = http::Response::builder()
+                .status(403)
+                .body(SdkBody::from(
+                    "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+                    <Message>denied</Message></Error><RequestId>1</RequestId></ErrorResponse>",
+                ))
+                .unwrap();
+            (request, response)
+        };
+        let conn = TestConnection::new(vec![denied(), denied()]);
+        let retry_queue = retry_queue(&get_mock_config().await, &conn);
+        let report = |batches| InvocationReport {
+            destinations: vec![DestinationReport {
+                delivery: DeliveryReport {
+                    batches,
+                    ..Default::default()
+                },
+                error: Some("throttled".into()),
+                retryable: true,
+                ..Default::default()
+            }],
+            retried: false,
+        };
+
+        // WHEN the events can't be sent to the queue
+        let failed = settle_delivery(&data, &report(0), Some(&retry_queue), 1).await;
+        let partial = settle_delivery(&data, &report(1), Some(&retry_queue), 1).await;
+
+        // THEN the source retries the batch only when nothing was delivered
+        assert_eq!(Settlement::Failed("throttled".into()), failed);
+        assert_eq!(Settlement::Dropped, partial);
+        conn.assert_requests_match(&[]);
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_undelivered() -> Result<(), RuntimeError> {
+        // GIVEN a batch delivered to CloudWatch Logs, and partially archived in S3
+        let events = (1..=3)
+            .map(|i| LogEntry {
+                id: i.to_string(),
+                timestamp: i,
+                message: "hello".into(),
+            })
+            .collect();
+        let data = log_data("DATA_MESSAGE").with_events(events);
+        let report = InvocationReport {
+            destinations: vec![
+                DestinationReport {
+                    destination: Some(SinkDestination::CloudWatchLogs(Destination {
+                        account: "123456789012".into(),
+                        log_group: "group".into(),
+                        log_stream: "stream".into(),
+                    })),
+                    ..Default::default()
+                },
+                DestinationReport {
+                    destination: Some(SinkDestination::S3(ArchiveDestination {
+                        account: "123456789012".into(),
+                        bucket: "bucket".into(),
+                        prefix: "function".into(),
+                    })),
+                    delivery: DeliveryReport {
+                        batches: 1,
+                        sent_ids: ["1".to_string()].into(),
+                        ..Default::default()
+                    },
+                    error: Some("throttled".into()),
+                    retryable: true,
+                    ..Default::default()
+                },
+            ],
+            retried: false,
+        };
+        let undelivered = data.with_events(data.log_events[1..].to_vec());
+        let body = RetryMessage::new(&undelivered, &[SinkKind::S3], "throttled", 1);
+        let conn = TestConnection::new(vec![send_message(
+            &serde_json::to_string(&body).unwrap(),
+            30,
+        )]);
+        let retry_queue = retry_queue(&get_mock_config().await, &conn);
+
+        // WHEN sending the undelivered events to the queue
+        retry_queue.enqueue_undelivered(&data, &report, 1).await?;
+
+        // THEN only the events that S3 didn't receive are retried, and only in S3
+        conn.assert_requests_match(&[]);
+        assert_eq!(1, conn.requests().len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_retry_messages() -> Result<(), RuntimeError> {
+        // GIVEN a store without the function and clients that fail if they are used
+        let config = get_mock_config().await;
+        let sts_client = StsClient::new(&config);
+        let function_store = MemoryStore::new([FunctionInfo {
+            id: "other".into(),
+            ..Default::default()
+        }]);
+        let retry = |message_type| {
+            serde_json::to_string(&RetryMessage::new(&log_data(message_type), &[], "error", 1))
+                .unwrap()
+        };
+        let event = SqsEvent {
+            records: vec![
+                message("1", retry("CONTROL_MESSAGE")),
+                message("2", retry("DATA_MESSAGE")),
+                message("3", "invalid".into()),
+            ],
+        };
+
+        // WHEN replaying the messages
+        let response = handle_retry_messages(
+            &sts_client,
+            &CredentialCache::new(),
+            &function_store,
+            &Config::default(),
+            &StreamCache::new(),
+            &RetryQueue::new(&config, "https://queue"),
+            LambdaEvent::new(event, Context::default()),
+        )
+        .await?;
+
+        // THEN the invalid message is retried, and the missing function is dropped
+        let failures: Vec<&str> = response
+            .batch_item_failures
+            .iter()
+            .map(|failure| failure.item_identifier.as_str())
+            .collect();
+        assert_eq!(vec!["3"], failures);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_retry_messages_attempts() -> Result<(), RuntimeError> {
+        // GIVEN a function whose role can't be assumed, and a batch that failed once
+        let data = log_data("DATA_MESSAGE");
+        let reason = "failed to assume role";
+        let body = |attempt| {
+            serde_json::to_string(&RetryMessage::new(&data, &[], reason, attempt)).unwrap()
+        };
+        let config = get_mock_config().await;
+        let sts_client = get_denied_sts_client(&config, 3);
+        let function_store = MemoryStore::new([function_info()]);
+        let conn = TestConnection::new(vec![
+            send_message(&body(2), 60),
+            send_message(&body(3), 120),
+        ]);
+        let retry_queue = retry_queue(&config, &conn);
+
+        // WHEN the replay fails twice, and then once more after the last attempt
+        let mut failures = Vec::new();
+        for attempt in [1, 2, MAX_ATTEMPTS] {
+            let event = SqsEvent {
+                records: vec![message("1", body(attempt))],
+            };
+            let response = handle_retry_messages(
+                &sts_client,
+                &CredentialCache::new(),
+                &function_store,
+                &Config::default(),
+                &StreamCache::new(),
+                &retry_queue,
+                LambdaEvent::new(event, Context::default()),
+            )
+            .await?;
+            failures.push(response.batch_item_failures.len());
+        }
+
+        // THEN the batch is sent back with the next attempt each time,
+        // until it's dropped without failing the message
+        conn.assert_requests_match(&[]);
+        assert_eq!(2, conn.requests().len());
+        assert_eq!(vec![0, 0, 0], failures);
+
+        Ok(())
+    }
+}
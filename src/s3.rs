@@ -0,0 +1,226 @@
+use crate::{
+    error::RuntimeError,
+    event::LogEntry,
+    filter::LogFilter,
+    naming::utc_date,
+    report::DeliveryReport,
+    sink::{LogSink, SinkDestination},
+};
+use async_trait::async_trait;
+use aws_sdk_s3::{types::ByteStream, Client, Error};
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// `ArchiveDestination` is the bucket in the customer account
+/// where the events are archived
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ArchiveDestination {
+    /// Id of the customer account
+    pub account: String,
+    /// Bucket in the customer account
+    pub bucket: String,
+    /// Prefix of the object keys, the function id
+    pub prefix: String,
+}
+
+/// `S3Sink` archives the events in a customer bucket, as gzipped
+/// newline delimited JSON objects.
+///
+/// Objects are only written when the bucket belongs to the customer account,
+/// so logs never end up in a mistyped or squatted bucket of another account.
+///
+/// Objects are partitioned by function and UTC date, with keys like
+/// `<function id>/YYYY/MM/DD/<first event id>.ndjson.gz`. Event ids are unique,
+/// so batches that are delivered again overwrite their objects
+/// instead of duplicating the events.
+pub struct S3Sink {
+    client: Client,
+    destination: ArchiveDestination,
+}
+
+impl S3Sink {
+    /// Create a sink for a bucket
+    pub fn new(client: Client, destination: ArchiveDestination) -> S3Sink {
+        S3Sink {
+            client,
+            destination,
+        }
+    }
+}
+
+/// Encode the events as gzipped newline delimited JSON
+fn encode_events(events: &[&LogEntry]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    for event in events {
+        serde_json::to_writer(&mut encoder, event).expect("failed to serialize log event");
+        encoder
+            .write_all(b"\n")
+            .expect("failed to compress log event");
+    }
+    encoder.finish().expect("failed to compress log events")
+}
+
+#[async_trait]
+impl LogSink for S3Sink {
+    fn destination(&self) -> SinkDestination {
+        SinkDestination::S3(self.destination.clone())
+    }
+
+    #[tracing::instrument(skip(self, filter, log_events))]
+    async fn send_events(
+        &self,
+        filter: &LogFilter,
+        log_events: &[LogEntry],
+        report: &mut DeliveryReport,
+    ) -> Result<(), RuntimeError> {
+        tracing::info!(bucket = %self.destination.bucket, "archiving logs in customer bucket");
+
+        let mut partitions: BTreeMap<String, Vec<&LogEntry>> = BTreeMap::new();
+        for event in log_events {
+            if filter.allows(event) {
+                partitions
+                    .entry(utc_date(event.timestamp))
+                    .or_default()
+                    .push(event);
+            } else {
+                report.filtered += 1;
+                report.skipped_ids.insert(event.id.clone());
+            }
+        }
+
+        for (date, events) in partitions {
+            let key = format!(
+                "{}/{date}/{}.ndjson.gz",
+                self.destination.prefix, events[0].id
+            );
+
+            self.client
+                .put_object()
+                .bucket(&self.destination.bucket)
+                .expected_bucket_owner(&self.destination.account)
+                .key(key)
+                .content_type("application/gzip")
+                .body(ByteStream::from(encode_events(&events)))
+                .send()
+                .await
+                .map_err(Error::from)?;
+
+            report.batches += 1;
+            report.events += events.len();
+            report
+                .sent_ids
+                .extend(events.iter().map(|event| event.id.clone()));
+        }
+
+        tracing::info!(
+            objects = report.batches,
+            events = report.events,
+            "logs archived"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        filter::{FilterAction, FilterRule, Matcher},
+        test_util::*,
+    };
+    use aws_sdk_s3::Config;
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+    use std::collections::HashSet;
+
+    fn event(id: &str, timestamp: i64, message: &str) -> LogEntry {
+        LogEntry {
+            id: id.into(),
+            timestamp,
+            message: message.into(),
+        }
+    }
+
+    fn put_object(key: &str, events: &[&LogEntry]) -> http::Request<SdkBody> {
+        http::Request::builder()
+            .uri(format!(
+                "https://s3.us-west-1.amazonaws.com/bucket/{key}?x-id=PutObject"
+            ))
+            .header("content-type", "application/gzip")
+            .header("x-amz-expected-bucket-owner", "123456789012")
+            .body(SdkBody::from(encode_events(events)))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_encode_events() {
+        let events = [event("1", 0, "hello"), event("2", 1, "world")];
+        let bytes = encode_events(&events.iter().collect::<Vec<_>>());
+
+        let mut json = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&bytes[..]), &mut json)
+            .unwrap();
+        assert_eq!(
+            "{\"id\":\"1\",\"timestamp\":0,\"message\":\"hello\"}\n\
+            {\"id\":\"2\",\"timestamp\":1,\"message\":\"world\"}\n",
+            json
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_events() -> Result<(), RuntimeError> {
+        // GIVEN events from two days, and a filter that skips one of them
+        let first = event("1", 1_552_435_200_000, "START RequestId");
+        let second = event("2", 1_552_435_201_000, "hello");
+        let third = event("3", 1_552_521_600_000, "world");
+        let filter = LogFilter::new(vec![FilterRule {
+            action: FilterAction::Exclude,
+            matcher: Matcher::Prefix("START".into()),
+        }]);
+
+        let ok = || {
+            http::Response::builder()
+                .status(200)
+                .body(SdkBody::from(""))
+                .unwrap()
+        };
+        let conn = TestConnection::new(vec![
+            (
+                put_object("function/2019/03/13/2.ndjson.gz", &[&second]),
+                ok(),
+            ),
+            (
+                put_object("function/2019/03/14/3.ndjson.gz", &[&third]),
+                ok(),
+            ),
+        ]);
+        let client = Client::from_conf_conn(
+            Config::new(&get_mock_config().await),
+            DynConnector::new(conn.clone()),
+        );
+        let sink = S3Sink::new(
+            client,
+            ArchiveDestination {
+                account: "123456789012".into(),
+                bucket: "bucket".into(),
+                prefix: "function".into(),
+            },
+        );
+
+        // WHEN archiving the events
+        let mut report = DeliveryReport::default();
+        sink.send_events(&filter, &[first, second, third], &mut report)
+            .await?;
+
+        // THEN an object is written for each day
+        conn.assert_requests_match(&[]);
+        assert_eq!(2, report.batches);
+        assert_eq!(2, report.events);
+        assert_eq!(1, report.filtered);
+        assert!(report.sent_ids.contains("2") && report.sent_ids.contains("3"));
+        assert_eq!(HashSet::from(["1".to_string()]), report.skipped_ids);
+
+        Ok(())
+    }
+}
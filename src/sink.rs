@@ -0,0 +1,52 @@
+use crate::{
+    cloudwatch_logs::Destination, error::RuntimeError, event::LogEntry, filter::LogFilter,
+    report::DeliveryReport, s3::ArchiveDestination,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// `SinkKind` is the type of a sink, used to retry only the sinks that failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    /// CloudWatch Logs in the customer account
+    CloudWatchLogs,
+    /// Archive bucket in the customer account
+    S3,
+}
+
+/// `SinkDestination` is where a sink delivers the events of a function
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "sink", rename_all = "snake_case")]
+pub enum SinkDestination {
+    /// Log stream in the customer account
+    CloudWatchLogs(Destination),
+    /// Archive bucket in the customer account
+    S3(ArchiveDestination),
+}
+
+impl SinkDestination {
+    /// Type of the sink that delivers to the destination
+    pub fn kind(&self) -> SinkKind {
+        match self {
+            SinkDestination::CloudWatchLogs(_) => SinkKind::CloudWatchLogs,
+            SinkDestination::S3(_) => SinkKind::S3,
+        }
+    }
+}
+
+/// `LogSink` delivers the events of a function to one of its destinations
+#[async_trait]
+pub trait LogSink: Send + Sync {
+    /// Destination of the events, for the delivery report
+    fn destination(&self) -> SinkDestination;
+
+    /// Send the events allowed by the filter,
+    /// recording what was delivered in the report
+    async fn send_events(
+        &self,
+        filter: &LogFilter,
+        log_events: &[LogEntry],
+        report: &mut DeliveryReport,
+    ) -> Result<(), RuntimeError>;
+}
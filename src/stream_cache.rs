@@ -0,0 +1,252 @@
+use crate::ttl_map::TtlMap;
+use std::time::{Duration, Instant};
+
+/// Time that groups stay in the cache, and streams after their last write
+pub const DEFAULT_STREAM_TTL: Duration = Duration::from_secs(3600);
+
+/// Number of groups, and of streams, in the cache
+pub const DEFAULT_MAX_STREAMS: usize = 1000;
+
+/// Key of a log group in a customer account
+type GroupKey = (String, String);
+
+/// Key of a log stream in a customer account
+type StreamKey = (String, String, String);
+
+/// `StreamCache` remembers the log groups and streams that exist in
+/// the customer accounts, and the last sequence token of each stream.
+///
+/// Lambda keeps the process alive between invocations, so the cache
+/// saves Describe* calls, which are heavily throttled, on warm invocations.
+///
+/// Stream names change over time, like the date bucketed ones,
+/// so streams expire when they are not written for a while,
+/// and the oldest streams are evicted when the cache is full.
+/// Groups expire and are evicted the same way, so containers that serve
+/// many accounts don't keep all of them.
+#[derive(Debug)]
+pub struct StreamCache {
+    ttl: Duration,
+    groups: TtlMap<GroupKey, ()>,
+    streams: TtlMap<StreamKey, Option<String>>,
+}
+
+impl Default for StreamCache {
+    fn default() -> Self {
+        StreamCache::with_limits(DEFAULT_STREAM_TTL, DEFAULT_MAX_STREAMS)
+    }
+}
+
+impl StreamCache {
+    /// Create an empty cache
+    pub fn new() -> StreamCache {
+        StreamCache::default()
+    }
+
+    /// Create an empty cache that keeps up to `max_size` groups for `ttl`,
+    /// and up to `max_size` streams for `ttl` after their last write.
+    pub fn with_limits(ttl: Duration, max_size: usize) -> StreamCache {
+        StreamCache {
+            ttl,
+            groups: TtlMap::new(max_size),
+            streams: TtlMap::new(max_size),
+        }
+    }
+
+    /// Check if a log group is known to exist
+    pub fn has_group(&self, account: &str, log_group: &str) -> bool {
+        self.has_group_at(account, log_group, Instant::now())
+    }
+
+    fn has_group_at(&self, account: &str, log_group: &str, now: Instant) -> bool {
+        self.groups
+            .get(&(account.to_owned(), log_group.to_owned()), now)
+            .is_some()
+    }
+
+    /// Remember that a log group exists
+    pub fn add_group(&self, account: &str, log_group: &str) {
+        self.add_group_at(account, log_group, Instant::now())
+    }
+
+    fn add_group_at(&self, account: &str, log_group: &str, now: Instant) {
+        let key = (account.to_owned(), log_group.to_owned());
+        self.groups.insert(key, (), self.ttl, now);
+    }
+
+    /// Forget a log group and all its streams
+    pub fn invalidate_group(&self, account: &str, log_group: &str) {
+        self.groups
+            .remove(&(account.to_owned(), log_group.to_owned()));
+        self.streams
+            .retain(|(a, g, _)| a != account || g != log_group);
+    }
+
+    /// Get the next sequence token of a stream.
+    /// It returns None if the stream is not in the cache, and Some(None)
+    /// if the stream exists but doesn't need a sequence token.
+    pub fn sequence_token(
+        &self,
+        account: &str,
+        log_group: &str,
+        log_stream: &str,
+    ) -> Option<Option<String>> {
+        self.sequence_token_at(account, log_group, log_stream, Instant::now())
+    }
+
+    fn sequence_token_at(
+        &self,
+        account: &str,
+        log_group: &str,
+        log_stream: &str,
+        now: Instant,
+    ) -> Option<Option<String>> {
+        self.streams
+            .get(&stream_key(account, log_group, log_stream), now)
+    }
+
+    /// Remember that a stream exists, and its next sequence token
+    pub fn set_sequence_token(
+        &self,
+        account: &str,
+        log_group: &str,
+        log_stream: &str,
+        sequence_token: Option<String>,
+    ) {
+        self.set_sequence_token_at(
+            account,
+            log_group,
+            log_stream,
+            sequence_token,
+            Instant::now(),
+        )
+    }
+
+    fn set_sequence_token_at(
+        &self,
+        account: &str,
+        log_group: &str,
+        log_stream: &str,
+        sequence_token: Option<String>,
+        now: Instant,
+    ) {
+        let key = stream_key(account, log_group, log_stream);
+        self.streams.insert(key, sequence_token, self.ttl, now);
+    }
+
+    /// Forget a stream and its sequence token
+    pub fn invalidate_stream(&self, account: &str, log_group: &str, log_stream: &str) {
+        self.streams
+            .remove(&stream_key(account, log_group, log_stream));
+    }
+}
+
+fn stream_key(account: &str, log_group: &str, log_stream: &str) -> StreamKey {
+    (
+        account.to_owned(),
+        log_group.to_owned(),
+        log_stream.to_owned(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_groups() {
+        let cache = StreamCache::new();
+        assert!(!cache.has_group("1", "group"));
+
+        cache.add_group("1", "group");
+        assert!(cache.has_group("1", "group"));
+        assert!(!cache.has_group("2", "group"));
+    }
+
+    #[test]
+    fn test_group_limits() {
+        let cache = StreamCache::with_limits(Duration::from_secs(10), 2);
+        let now = Instant::now();
+
+        cache.add_group_at("1", "a", now);
+        cache.add_group_at("1", "b", now + Duration::from_secs(1));
+        cache.add_group_at("1", "c", now + Duration::from_secs(2));
+        assert!(!cache.has_group_at("1", "a", now));
+        assert!(cache.has_group_at("1", "b", now));
+        assert!(cache.has_group_at("1", "c", now));
+
+        assert!(!cache.has_group_at("1", "b", now + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_sequence_tokens() {
+        let cache = StreamCache::new();
+        assert_eq!(None, cache.sequence_token("1", "group", "stream"));
+
+        cache.set_sequence_token("1", "group", "stream", None);
+        assert_eq!(Some(None), cache.sequence_token("1", "group", "stream"));
+
+        cache.set_sequence_token("1", "group", "stream", Some("token".into()));
+        assert_eq!(
+            Some(Some("token".into())),
+            cache.sequence_token("1", "group", "stream")
+        );
+
+        cache.invalidate_stream("1", "group", "stream");
+        assert_eq!(None, cache.sequence_token("1", "group", "stream"));
+    }
+
+    #[test]
+    fn test_invalidate_group() {
+        let cache = StreamCache::new();
+        cache.add_group("1", "group");
+        cache.set_sequence_token("1", "group", "stream", None);
+        cache.set_sequence_token("1", "other", "stream", None);
+
+        cache.invalidate_group("1", "group");
+        assert!(!cache.has_group("1", "group"));
+        assert_eq!(None, cache.sequence_token("1", "group", "stream"));
+        assert_eq!(Some(None), cache.sequence_token("1", "other", "stream"));
+    }
+
+    #[test]
+    fn test_stream_expiration() {
+        let cache = StreamCache::with_limits(Duration::from_secs(10), 10);
+        let now = Instant::now();
+
+        cache.set_sequence_token_at("1", "group", "stream", Some("token".into()), now);
+        assert!(cache
+            .sequence_token_at("1", "group", "stream", now + Duration::from_secs(5))
+            .is_some());
+
+        // writes keep the stream in the cache
+        cache.set_sequence_token_at(
+            "1",
+            "group",
+            "stream",
+            Some("next".into()),
+            now + Duration::from_secs(5),
+        );
+        assert!(cache
+            .sequence_token_at("1", "group", "stream", now + Duration::from_secs(11))
+            .is_some());
+        assert_eq!(
+            None,
+            cache.sequence_token_at("1", "group", "stream", now + Duration::from_secs(16))
+        );
+    }
+
+    #[test]
+    fn test_max_streams() {
+        let cache = StreamCache::with_limits(Duration::from_secs(10), 2);
+        let now = Instant::now();
+
+        cache.set_sequence_token_at("1", "group", "a", None, now);
+        cache.set_sequence_token_at("1", "group", "b", None, now + Duration::from_secs(1));
+        cache.set_sequence_token_at("1", "group", "c", None, now + Duration::from_secs(2));
+
+        assert_eq!(None, cache.sequence_token_at("1", "group", "a", now));
+        assert!(cache.sequence_token_at("1", "group", "b", now).is_some());
+        assert!(cache.sequence_token_at("1", "group", "c", now).is_some());
+    }
+}
@@ -1,25 +1,66 @@
 use crate::error::RuntimeError;
 use aws_sdk_iam::Credentials;
-use aws_sdk_sts::{Client, Error};
-use aws_types::SdkConfig;
+use aws_sdk_sts::{model::Tag, Client, Error};
+use aws_types::{
+    credentials::{future, CredentialsError, ProvideCredentials},
+    SdkConfig,
+};
+use std::{
+    collections::BTreeMap,
+    ops::RangeInclusive,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Time before the assumed credentials expire when they are refreshed,
+/// so requests in flight don't fail with expired credentials.
+pub const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// Session durations accepted by STS, in seconds
+pub const SESSION_DURATION_SECONDS: RangeInclusive<i32> = 900..=43200;
+
+/// `AssumeRoleOptions` are the optional parameters of AssumeRole
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AssumeRoleOptions {
+    /// External id required by the trust policy of the role
+    pub external_id: Option<String>,
+    /// Duration of the role session, in seconds
+    pub duration_seconds: Option<i32>,
+    /// Tags attached to the role session
+    pub session_tags: BTreeMap<String, String>,
+    /// Identity of the principal that assumes the role, recorded in CloudTrail
+    pub source_identity: Option<String>,
+}
 
 /// Assume a new role to perform operations in a different account.
+/// The credentials keep the expiration time returned by STS.
 ///
 /// TODO(david): is the assume_role_arn considered private information that
 /// we cannot have in our service logs? if it's private, add it to the `skip` attribute
 /// in the instrument macro below.
-#[tracing::instrument(skip(client))]
+#[tracing::instrument(skip(client, options))]
 pub async fn assume_role(
     client: &Client,
     session_id: &str,
     assume_role_arn: &str,
-) -> Result<SdkConfig, RuntimeError> {
+    options: &AssumeRoleOptions,
+) -> Result<Credentials, RuntimeError> {
     tracing::info!("assuming new role role");
 
+    let tags = options
+        .session_tags
+        .iter()
+        .map(|(key, value)| Tag::builder().key(key).value(value).build())
+        .collect::<Vec<_>>();
+
     let assumed_role = client
         .assume_role()
         .role_arn(assume_role_arn)
         .role_session_name(session_id)
+        .set_external_id(options.external_id.clone())
+        .set_duration_seconds(options.duration_seconds)
+        .set_tags(Some(tags).filter(|tags| !tags.is_empty()))
+        .set_source_identity(options.source_identity.clone())
         .send()
         .await
         .map_err(Error::from)?;
@@ -35,16 +76,334 @@ pub async fn assume_role(
             _ => return Err(RuntimeError::MissingCredentials),
         };
 
-    let assumed_credentials = Credentials::from_keys(
+    let expiration = credentials
+        .expiration()
+        .and_then(|expiration| SystemTime::try_from(*expiration).ok());
+
+    Ok(Credentials::new(
         access_key_id,
         secret_access_key,
         credentials.session_token.clone(),
-    );
+        expiration,
+        "AssumeRoleProvider",
+    ))
+}
+
+/// `AssumeRoleProvider` provides the credentials of an assumed role,
+/// assuming the role again shortly before the credentials expire.
+///
+/// Clients created with this provider can live longer than the role session,
+/// like the clients kept between warm invocations.
+#[derive(Debug)]
+pub struct AssumeRoleProvider {
+    client: Client,
+    session_id: String,
+    role_arn: String,
+    options: AssumeRoleOptions,
+    credentials: Mutex<Option<Credentials>>,
+}
+
+impl AssumeRoleProvider {
+    /// Create a provider for a role. The role is assumed on the first request.
+    pub fn new(client: &Client, session_id: &str, role_arn: &str) -> AssumeRoleProvider {
+        AssumeRoleProvider {
+            client: client.clone(),
+            session_id: session_id.to_owned(),
+            role_arn: role_arn.to_owned(),
+            options: AssumeRoleOptions::default(),
+            credentials: Mutex::new(None),
+        }
+    }
+
+    /// Set the optional parameters used to assume the role
+    pub fn with_options(mut self, options: AssumeRoleOptions) -> AssumeRoleProvider {
+        self.options = options;
+        self
+    }
+
+    /// Get the current credentials, assuming the role again if they are about to expire
+    pub async fn credentials(&self) -> Result<Credentials, RuntimeError> {
+        if let Some(credentials) = self.fresh_credentials(SystemTime::now()) {
+            return Ok(credentials);
+        }
+
+        let credentials = assume_role(
+            &self.client,
+            &self.session_id,
+            &self.role_arn,
+            &self.options,
+        )
+        .await?;
+        *self.credentials.lock().unwrap() = Some(credentials.clone());
+        Ok(credentials)
+    }
 
-    let new_config = aws_config::from_env()
-        .credentials_provider(assumed_credentials)
+    fn fresh_credentials(&self, now: SystemTime) -> Option<Credentials> {
+        self.credentials
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|credentials| is_fresh(credentials.expiry(), now))
+    }
+}
+
+impl ProvideCredentials for AssumeRoleProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// Check if credentials are valid for longer than the refresh margin.
+/// Credentials without expiration are always refreshed.
+fn is_fresh(expiration: Option<SystemTime>, now: SystemTime) -> bool {
+    match expiration {
+        Some(expiration) => now + REFRESH_MARGIN < expiration,
+        None => false,
+    }
+}
+
+/// Load the AWS configuration with the credentials of an assumed role.
+/// The role is assumed right away, so invalid roles fail here
+/// instead of in the first request of the clients.
+pub async fn assumed_config(provider: AssumeRoleProvider) -> Result<SdkConfig, RuntimeError> {
+    provider.credentials().await?;
+
+    Ok(aws_config::from_env()
+        .credentials_provider(provider)
         .load()
-        .await;
+        .await)
+}
+
+/// Prefix of the role session names, to recognize the processor sessions in CloudTrail
+pub const SESSION_NAME_PREFIX: &str = "cwlogs";
+
+/// Maximum length of a role session name
+const MAX_SESSION_NAME_LEN: usize = 64;
+
+/// Maximum length of the unique part of a role session name,
+/// enough for a UUID like the Lambda request ids
+const MAX_SESSION_UNIQUE_LEN: usize = 36;
+
+/// Build a role session name like `cwlogs.<hint>.<unique>`.
+///
+/// The hint identifies the tenant or function in CloudTrail, and the unique part,
+/// like a request id, tells sessions apart. STS only accepts 2 to 64 characters
+/// in `[\w+=,.@-]`, so other characters are replaced with `-` and the hint
+/// is truncated to fit.
+pub fn session_name(hint: &str, unique: &str) -> String {
+    let unique: String = sanitize_session_name(unique)
+        .take(MAX_SESSION_UNIQUE_LEN)
+        .collect();
+
+    let mut name = SESSION_NAME_PREFIX.to_owned();
+    let hint_len = MAX_SESSION_NAME_LEN - name.len() - unique.len() - 2;
+    if !hint.is_empty() {
+        name.push('.');
+        name.extend(sanitize_session_name(hint).take(hint_len));
+    }
+    if !unique.is_empty() {
+        name.push('.');
+        name.push_str(&unique);
+    }
+    name
+}
+
+/// Replace the characters that STS doesn't accept in session names
+fn sanitize_session_name(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().map(|c| {
+        if c.is_ascii_alphanumeric() || "_+=,.@-".contains(c) {
+            c
+        } else {
+            '-'
+        }
+    })
+}
+
+/// Extract the account id from a role ARN,
+/// like `123456789012` in `arn:aws:iam::123456789012:role/name`.
+pub fn account_id(role_arn: &str) -> Option<&str> {
+    role_arn
+        .strip_prefix("arn:")?
+        .split(':')
+        .nth(3)
+        .filter(|id| !id.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::*;
+    use aws_sdk_sts::Config;
+    use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+    use aws_smithy_http::body::SdkBody;
+    use proptest::prelude::*;
+
+    fn assume_role_request() -> http::Request<SdkBody> {
+        get_request_builder("sts")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(SdkBody::from(
+                "Action=AssumeRole&Version=2011-06-15&RoleArn=arn&RoleSessionName=session",
+            ))
+            .unwrap()
+    }
+
+    fn assume_role_response(expiration: &str) -> http::Response<SdkBody> {
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::from(format!(
+                r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+                <AssumeRoleResult>
+                    <Credentials>
+                        <AccessKeyId>id</AccessKeyId>
+                        <SecretAccessKey>secret</SecretAccessKey>
+                        <SessionToken>token</SessionToken>
+                        <Expiration>{expiration}</Expiration>
+                    </Credentials>
+                </AssumeRoleResult>
+                </AssumeRoleResponse>"#
+            )))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_assume_role_provider() -> Result<(), RuntimeError> {
+        // GIVEN STS credentials that expire far in the future
+        let conn = TestConnection::new(vec![(
+            assume_role_request(),
+            assume_role_response("2100-01-01T00:00:00Z"),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+        let provider = AssumeRoleProvider::new(&client, "session", "arn");
+
+        // WHEN requesting credentials twice
+        let credentials = provider.provide_credentials().await.unwrap();
+        provider.provide_credentials().await.unwrap();
+
+        // THEN the role is only assumed once
+        assert_eq!("id", credentials.access_key_id());
+        assert_eq!(Some("token"), credentials.session_token());
+        assert!(credentials.expiry().is_some());
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_assume_role_with_options() -> Result<(), RuntimeError> {
+        // GIVEN a role that requires an external id
+        let conn = TestConnection::new(vec![(
+            get_request_builder("sts")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(SdkBody::from(
+                    "Action=AssumeRole&Version=2011-06-15&RoleArn=arn&RoleSessionName=session\
+                    &DurationSeconds=3600&Tags.member.1.Key=FunctionId&Tags.member.1.Value=1\
+                    &ExternalId=external&SourceIdentity=processor",
+                ))
+                .unwrap(),
+            assume_role_response("2100-01-01T00:00:00Z"),
+        )]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+        let options = AssumeRoleOptions {
+            external_id: Some("external".into()),
+            duration_seconds: Some(3600),
+            session_tags: BTreeMap::from([("FunctionId".into(), "1".into())]),
+            source_identity: Some("processor".into()),
+        };
+
+        // WHEN assuming the role with options
+        assume_role(&client, "session", "arn", &options).await?;
+
+        // THEN the options are sent to STS
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_assume_role_provider_refresh() -> Result<(), RuntimeError> {
+        // GIVEN STS credentials that are already about to expire
+        let conn = TestConnection::new(vec![
+            (
+                assume_role_request(),
+                assume_role_response("2000-01-01T00:00:00Z"),
+            ),
+            (
+                assume_role_request(),
+                assume_role_response("2100-01-01T00:00:00Z"),
+            ),
+        ]);
+        let config = Config::new(&get_mock_config().await);
+        let client = Client::from_conf_conn(config, DynConnector::new(conn.clone()));
+        let provider = AssumeRoleProvider::new(&client, "session", "arn");
+
+        // WHEN requesting credentials twice
+        provider.credentials().await?;
+        provider.credentials().await?;
+
+        // THEN the role is assumed again
+        conn.assert_requests_match(&[]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_id() {
+        assert_eq!(
+            Some("123456789012"),
+            account_id("arn:aws:iam::123456789012:role/name")
+        );
+        assert_eq!(None, account_id("arn:aws:iam:::role/name"));
+        assert_eq!(None, account_id("not an arn"));
+    }
+
+    fn is_valid_session_name(name: &str) -> bool {
+        (2..=64).contains(&name.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+=,.@-".contains(c))
+    }
+
+    #[test]
+    fn test_session_name() {
+        assert_eq!("cwlogs.function.1234", session_name("function", "1234"));
+        assert_eq!(
+            "cwlogs.my-function-.c6af9ac6-7b61-11e6-9a41-93e8deadbeef",
+            session_name("my function!", "c6af9ac6-7b61-11e6-9a41-93e8deadbeef")
+        );
+        assert_eq!("cwlogs", session_name("", ""));
+
+        let name = session_name(&"f".repeat(100), &"1".repeat(100));
+        assert_eq!(64, name.len());
+        assert!(name.ends_with(&format!(".{}", "1".repeat(36))));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_session_name_is_valid(hint in ".*", unique in ".*") {
+            let name = session_name(&hint, &unique);
+            prop_assert!(is_valid_session_name(&name), "invalid session name {}", name);
+        }
+
+        #[test]
+        fn prop_session_name_keeps_unique_part(hint in ".*", unique in "[a-f0-9]{32}") {
+            let name = session_name(&hint, &unique);
+            prop_assert!(name.ends_with(&unique));
+        }
 
-    Ok(new_config)
+        #[test]
+        fn prop_session_name_keeps_short_hint(hint in "[a-zA-Z0-9_-]{1,20}", unique in "[a-f0-9-]{0,36}") {
+            let name = session_name(&hint, &unique);
+            let prefix = format!("{SESSION_NAME_PREFIX}.{hint}");
+            prop_assert!(name.starts_with(&prefix));
+        }
+    }
 }
@@ -0,0 +1,123 @@
+use crate::error::RuntimeError;
+use std::{collections::HashMap, fmt};
+
+/// Piece of a template
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    /// Text copied as is
+    Literal(String),
+    /// Name of a variable replaced when the template is rendered
+    Variable(String),
+}
+
+/// `Template` is a string with `{variable}` placeholders,
+/// like `{prefix}/{app_id}/{branch}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Parse a template, making sure that it only uses the allowed variables
+    pub fn parse(source: &str, allowed: &[&str]) -> Result<Template, RuntimeError> {
+        let invalid = |reason: String| RuntimeError::InvalidTemplate(format!("{source}: {reason}"));
+
+        let mut segments = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| invalid("unclosed placeholder".into()))?;
+
+            let name = &rest[start + 1..end];
+            if !allowed.contains(&name) {
+                return Err(invalid(format!("unknown variable {name}")));
+            }
+            segments.push(Segment::Variable(name.to_owned()));
+
+            rest = &rest[end + 1..];
+        }
+
+        if rest.contains('}') {
+            return Err(invalid("unopened placeholder".into()));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        Ok(Template {
+            source: source.to_owned(),
+            segments,
+        })
+    }
+
+    /// Replace the placeholders with the variable values.
+    /// All the variables in the template must have a value.
+    pub fn render(&self, variables: &HashMap<&str, &str>) -> Result<String, RuntimeError> {
+        let mut output = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => output.push_str(text),
+                Segment::Variable(name) => match variables.get(name.as_str()) {
+                    Some(value) => output.push_str(value),
+                    None => {
+                        return Err(RuntimeError::InvalidTemplate(format!(
+                            "{}: missing value for variable {name}",
+                            self.source
+                        )))
+                    }
+                },
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let template = Template::parse("/{prefix}/{app}/x-{app}", &["prefix", "app"]).unwrap();
+        let variables = HashMap::from([("prefix", "logs"), ("app", "web")]);
+
+        assert_eq!("/logs/web/x-web", template.render(&variables).unwrap());
+    }
+
+    #[test]
+    fn test_render_template_without_variables() {
+        let template = Template::parse("static", &[]).unwrap();
+        assert_eq!("static", template.render(&HashMap::new()).unwrap());
+    }
+
+    #[test]
+    fn test_render_template_with_missing_variable() {
+        let template = Template::parse("{prefix}/{app}", &["prefix", "app"]).unwrap();
+        let variables = HashMap::from([("prefix", "logs")]);
+
+        assert!(template.render(&variables).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_templates() {
+        assert!(Template::parse("{prefix", &["prefix"]).is_err());
+        assert!(Template::parse("prefix}", &["prefix"]).is_err());
+        assert!(Template::parse("{other}", &["prefix"]).is_err());
+    }
+}
@@ -1,4 +1,11 @@
+use crate::{
+    event::{LogData, LogEntry},
+    function_info::FunctionInfo,
+    FirehoseEventRecord, KinesisEventRecord, KinesisRecord, RetryQueue,
+};
 use aws_sdk_iam::Credentials;
+use aws_smithy_client::{erase::DynConnector, test_connection::TestConnection};
+use aws_smithy_http::{body::SdkBody, query};
 use aws_types::{region::Region, SdkConfig};
 
 /// Configuration for mocking AWS SDK clients
@@ -20,3 +27,115 @@ pub async fn get_mock_config() -> SdkConfig {
 pub fn get_request_builder(service: &str) -> http::request::Builder {
     http::Request::builder().uri(format!("https://{service}.us-west-1.amazonaws.com/"))
 }
+
+/// STS client that fails to assume roles with an access denied error,
+/// for the given number of attempts
+pub fn get_denied_sts_client(config: &SdkConfig, attempts: usize) -> aws_sdk_sts::Client {
+    let denied = || {
+        (
+            get_request_builder("sts").body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(403)
+                .body(SdkBody::from(
+                    "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+                    <Message>denied</Message></Error></ErrorResponse>",
+                ))
+                .unwrap(),
+        )
+    };
+    let conn = TestConnection::new((0..attempts).map(|_| denied()).collect());
+    aws_sdk_sts::Client::from_conf_conn(aws_sdk_sts::Config::new(config), DynConnector::new(conn))
+}
+
+/// Gzip and base64 encode a log data payload, like CloudWatch does
+/// for its subscription destinations
+pub fn encode_log_data(json: &str) -> String {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(json.as_bytes()).unwrap();
+    base64::encode(encoder.finish().unwrap())
+}
+
+/// Log data of the function, with a single event
+pub fn log_data(message_type: &str) -> LogData {
+    LogData {
+        log_group: "/aws/lambda/function".into(),
+        log_stream: "stream".into(),
+        message_type: message_type.into(),
+        log_events: vec![LogEntry {
+            id: "1".into(),
+            timestamp: 0,
+            message: "hello".into(),
+        }],
+        ..Default::default()
+    }
+}
+
+/// Encoded log data of the function, with a single event in the given log stream
+pub fn encoded_log_data(message_type: &str, log_stream: &str, id: &str) -> String {
+    encode_log_data(&format!(
+        r#"{{"owner": "123456789012", "logGroup": "/aws/lambda/function",
+            "logStream": "{log_stream}", "subscriptionFilters": ["filter"],
+            "messageType": "{message_type}",
+            "logEvents": [{{"id": "{id}", "timestamp": 0, "message": "hello"}}]}}"#
+    ))
+}
+
+/// Function of the log data, delivered with a role of the customer account
+pub fn function_info() -> FunctionInfo {
+    FunctionInfo {
+        id: "function".into(),
+        cloudwatch_logs_assume_role_arn: "arn:aws:iam::123456789012:role/logs".into(),
+        ..Default::default()
+    }
+}
+
+/// Kinesis record with the given data
+pub fn kinesis_record(sequence_number: &str, data: String) -> KinesisEventRecord {
+    KinesisEventRecord {
+        kinesis: KinesisRecord {
+            sequence_number: sequence_number.into(),
+            partition_key: "key".into(),
+            data,
+        },
+    }
+}
+
+/// Firehose record with the log data of the function
+pub fn firehose_record(record_id: &str, message_type: &str) -> FirehoseEventRecord {
+    FirehoseEventRecord {
+        record_id: record_id.into(),
+        data: encoded_log_data(message_type, "stream", "1"),
+    }
+}
+
+/// SendMessage request of a retry message, and its successful response
+pub fn send_message(body: &str, delay: i32) -> (http::Request<SdkBody>, http::Response<SdkBody>) {
+    (
+        get_request_builder("sqs")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(SdkBody::from(format!(
+                "Action=SendMessage&Version=2012-11-05&QueueUrl={}&MessageBody={}&DelaySeconds={delay}",
+                query::fmt_string("https://queue"),
+                query::fmt_string(body)
+            )))
+            .unwrap(),
+        http::Response::builder()
+            .status(200)
+            .body(SdkBody::from(
+                "<SendMessageResponse><SendMessageResult><MessageId>1</MessageId>\
+                </SendMessageResult></SendMessageResponse>",
+            ))
+            .unwrap(),
+    )
+}
+
+/// Retry queue that sends its messages through the test connection
+pub fn retry_queue(config: &SdkConfig, conn: &TestConnection<SdkBody>) -> RetryQueue {
+    let client = aws_sdk_sqs::Client::from_conf_conn(
+        aws_sdk_sqs::Config::new(config),
+        DynConnector::new(conn.clone()),
+    );
+    RetryQueue::from_client(client, "https://queue")
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct TtlEntry<V> {
+    value: V,
+    expires_at: Instant,
+}
+
+/// `TtlMap` is the storage of the in-memory caches: a map whose entries expire,
+/// with a maximum number of entries.
+///
+/// When the map is full, expired entries are removed first,
+/// and then the entry that expires the soonest.
+/// A map with `max_size` 0 doesn't store anything.
+///
+/// Operations take the current time, so the caches can be tested
+/// without waiting for their entries to expire.
+#[derive(Debug)]
+pub struct TtlMap<K, V> {
+    max_size: usize,
+    entries: Mutex<HashMap<K, TtlEntry<V>>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> TtlMap<K, V> {
+    /// Create an empty map that keeps up to `max_size` entries
+    pub fn new(max_size: usize) -> TtlMap<K, V> {
+        TtlMap {
+            max_size,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the value of an entry that didn't expire
+    pub fn get(&self, key: &K, now: Instant) -> Option<V> {
+        self.get_entry(key, now, None)
+    }
+
+    /// Get the value of an entry that didn't expire,
+    /// and keep it for `ttl` from now
+    pub fn get_and_extend(&self, key: &K, ttl: Duration, now: Instant) -> Option<V> {
+        self.get_entry(key, now, Some(ttl))
+    }
+
+    fn get_entry(&self, key: &K, now: Instant, ttl: Option<Duration>) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                if let Some(ttl) = ttl {
+                    entry.expires_at = now + ttl;
+                }
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Store a value for `ttl` from now, evicting an entry when the map is full
+    pub fn insert(&self, key: K, value: V, ttl: Duration, now: Instant) {
+        if self.max_size == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_size && !entries.contains_key(&key) {
+            entries.retain(|_, e| e.expires_at > now);
+        }
+        if entries.len() >= self.max_size && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.expires_at)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key,
+            TtlEntry {
+                value,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    /// Remove an entry
+    pub fn remove(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// Keep only the entries whose key matches the predicate
+    pub fn retain(&self, mut f: impl FnMut(&K) -> bool) {
+        self.entries.lock().unwrap().retain(|k, _| f(k));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn test_expiration() {
+        let map = TtlMap::new(10);
+        let now = Instant::now();
+
+        map.insert("a", 1, TTL, now);
+        map.insert("b", 2, Duration::from_secs(5), now);
+
+        assert_eq!(Some(1), map.get(&"a", now));
+        assert_eq!(Some(2), map.get(&"b", now));
+        assert_eq!(None, map.get(&"c", now));
+
+        let later = now + Duration::from_secs(6);
+        assert_eq!(Some(1), map.get(&"a", later));
+        assert_eq!(None, map.get(&"b", later));
+        assert_eq!(None, map.get(&"a", now + TTL));
+    }
+
+    #[test]
+    fn test_get_and_extend() {
+        let map = TtlMap::new(10);
+        let now = Instant::now();
+
+        map.insert("a", 1, TTL, now);
+        let later = now + Duration::from_secs(8);
+        assert_eq!(Some(1), map.get_and_extend(&"a", TTL, later));
+        assert_eq!(Some(1), map.get(&"a", later + Duration::from_secs(8)));
+        assert_eq!(None, map.get(&"a", later + TTL));
+    }
+
+    #[test]
+    fn test_max_size() {
+        let map = TtlMap::new(2);
+        let now = Instant::now();
+
+        // the entry that expires the soonest is evicted
+        map.insert("a", 1, TTL, now);
+        map.insert("b", 2, TTL, now + Duration::from_secs(1));
+        map.insert("c", 3, TTL, now + Duration::from_secs(2));
+        assert_eq!(None, map.get(&"a", now));
+        assert_eq!(Some(2), map.get(&"b", now));
+        assert_eq!(Some(3), map.get(&"c", now));
+
+        // replacing an entry doesn't evict another one
+        map.insert("c", 4, TTL, now + Duration::from_secs(3));
+        assert_eq!(Some(2), map.get(&"b", now));
+        assert_eq!(Some(4), map.get(&"c", now));
+
+        // expired entries are evicted first
+        let later = now + Duration::from_secs(12);
+        map.insert("d", 5, TTL, later);
+        map.insert("e", 6, TTL, later);
+        assert_eq!(None, map.get(&"c", later));
+        assert_eq!(Some(5), map.get(&"d", later));
+        assert_eq!(Some(6), map.get(&"e", later));
+    }
+
+    #[test]
+    fn test_remove_and_retain() {
+        let map = TtlMap::new(10);
+        let now = Instant::now();
+        map.insert("a", 1, TTL, now);
+        map.insert("b", 2, TTL, now);
+        map.insert("c", 3, TTL, now);
+
+        map.remove(&"a");
+        map.retain(|k| *k != "b");
+        assert_eq!(None, map.get(&"a", now));
+        assert_eq!(None, map.get(&"b", now));
+        assert_eq!(Some(3), map.get(&"c", now));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let map = TtlMap::new(0);
+        let now = Instant::now();
+
+        map.insert("a", 1, TTL, now);
+        assert_eq!(None, map.get(&"a", now));
+    }
+}